@@ -4,8 +4,11 @@ use std::{
     io,
     io::{BufRead as _, BufReader},
     path::PathBuf,
-    sync::atomic::{AtomicU64, Ordering},
-    time::Instant,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 use clap::Parser;
@@ -37,6 +40,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     let oks = AtomicU64::new(0);
     let bad_requests = AtomicU64::new(0);
     let others = AtomicU64::new(0);
+    let latencies = Mutex::new(Vec::with_capacity(fens.len()));
     let before_all = Instant::now();
 
     // With RAYON_NUM_THREADS
@@ -45,7 +49,7 @@ fn main() -> Result<(), Box<dyn Error>> {
 
         let res = client
             .get(&opt.endpoint)
-            .query(&[("fen", fen)])
+            .query(&[("fen", fen), ("multi_pv", &opt.multi_pv.to_string())])
             .send()
             .expect("send");
 
@@ -57,13 +61,31 @@ fn main() -> Result<(), Box<dyn Error>> {
         .fetch_add(1, Ordering::Relaxed);
 
         let _ = res.text().expect("text");
-        println!("{}\t{}", fen, before.elapsed().as_millis());
+        let elapsed = before.elapsed();
+        latencies.lock().unwrap().push(elapsed);
+        println!("{}\t{}", fen, elapsed.as_millis());
     });
 
+    let mut latencies = latencies.into_inner().unwrap();
+    latencies.sort_unstable();
+    let percentile = |p: usize| -> Duration {
+        latencies
+            .get((latencies.len() * p / 100).min(latencies.len().saturating_sub(1)))
+            .copied()
+            .unwrap_or_default()
+    };
+
     eprintln!("total elapsed: {:.3?}", before_all.elapsed());
     eprintln!("ok: {}", oks.load(Ordering::Relaxed));
     eprintln!("bad requests: {}", bad_requests.load(Ordering::Relaxed));
     eprintln!("others: {}", others.load(Ordering::Relaxed));
+    eprintln!(
+        "latency: p50 {:.3?}, p90 {:.3?}, p99 {:.3?}, max {:.3?}",
+        percentile(50),
+        percentile(90),
+        percentile(99),
+        percentile(100)
+    );
 
     Ok(())
 }
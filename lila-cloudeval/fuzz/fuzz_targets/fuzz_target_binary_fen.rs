@@ -6,6 +6,13 @@ use shakmaty::variant::Variant;
 use lila_cloudeval::binary_fen::VariantSetup;
 
 fuzz_target!(|data: &[u8]| {
+    // Must not panic on arbitrary input
+    if let Ok(setup) = VariantSetup::try_read(&mut &data[..]) {
+        let mut buf = Vec::new();
+        setup.write(&mut buf);
+        let _ = VariantSetup::try_read(&mut &buf[..]);
+    }
+
     if let Ok(fen) = Fen::from_ascii(data) {
         let variant = Variant::Chess;
         let original = VariantSetup::new_normalized(fen.into_setup(), variant);
@@ -13,7 +20,7 @@ fuzz_target!(|data: &[u8]| {
         let mut buf = Vec::new();
         original.write(&mut buf);
 
-        let roundtripped = VariantSetup::read(&mut &buf[..]);
+        let roundtripped = VariantSetup::try_read(&mut &buf[..]).expect("roundtrip");
         assert_eq!(original, roundtripped);
     }
 });
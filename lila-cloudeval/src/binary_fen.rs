@@ -5,6 +5,21 @@ use shakmaty::{
     variant::Variant, Bitboard, ByColor, ByRole, Color, Piece, Rank, RemainingChecks, Role, Setup,
     Square,
 };
+use thiserror::Error;
+
+#[derive(Error, Debug, Eq, PartialEq)]
+pub enum BinaryFenError {
+    #[error("unexpected end of binary fen")]
+    Truncated,
+    #[error("invalid nibble in binary fen: {nibble}")]
+    InvalidNibble { nibble: u8 },
+    #[error("invalid variant header in binary fen: {header}")]
+    InvalidVariant { header: u8 },
+    #[error("trailing bytes after binary fen")]
+    TrailingBytes,
+    #[error("overlong leb128 in binary fen")]
+    OverlongLeb128,
+}
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct VariantSetup {
@@ -18,9 +33,9 @@ fn write_nibbles<B: BufMut>(lo: u8, hi: u8, buf: &mut B) {
     buf.put_u8(lo | (hi << 4));
 }
 
-fn read_nibbles<B: Buf>(buf: &mut B) -> (u8, u8) {
-    let byte = buf.get_u8();
-    (byte & 0xf, byte >> 4)
+fn read_nibbles<B: Buf>(buf: &mut B) -> Result<(u8, u8), BinaryFenError> {
+    let byte = read_u8(buf)?;
+    Ok((byte & 0xf, byte >> 4))
 }
 
 fn write_leb128<B: BufMut>(mut n: u32, buf: &mut B) {
@@ -31,18 +46,42 @@ fn write_leb128<B: BufMut>(mut n: u32, buf: &mut B) {
     buf.put_u8(n as u8);
 }
 
-fn read_leb128<B: Buf>(buf: &mut B) -> u32 {
-    let mut n = 0;
+fn read_leb128<B: Buf>(buf: &mut B) -> Result<u32, BinaryFenError> {
+    let mut n: u32 = 0;
     let mut shift = 0;
     while buf.has_remaining() {
         let byte = buf.get_u8();
-        n |= u32::from(byte & 127) << shift;
+        let bits = u32::from(byte & 127);
+        if shift >= u32::BITS || (bits << shift) >> shift != bits {
+            return Err(BinaryFenError::OverlongLeb128);
+        }
+        n |= bits << shift;
         shift += 7;
         if byte & 128 == 0 {
-            break;
+            return Ok(n);
         }
     }
-    n
+    if shift == 0 {
+        Ok(0) // Omitted
+    } else {
+        Err(BinaryFenError::Truncated)
+    }
+}
+
+fn read_u8<B: Buf>(buf: &mut B) -> Result<u8, BinaryFenError> {
+    if buf.has_remaining() {
+        Ok(buf.get_u8())
+    } else {
+        Err(BinaryFenError::Truncated)
+    }
+}
+
+fn read_u64<B: Buf>(buf: &mut B) -> Result<u64, BinaryFenError> {
+    if buf.remaining() >= 8 {
+        Ok(buf.get_u64())
+    } else {
+        Err(BinaryFenError::Truncated)
+    }
 }
 
 fn read_byte<B: Buf>(buf: &mut B) -> u8 {
@@ -171,11 +210,21 @@ impl VariantSetup {
         }
     }
 
+    /// Reads a binary fen.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the binary fen is invalid. Use
+    /// [`VariantSetup::try_read()`] for untrusted input.
     pub fn read<B: Buf>(buf: &mut B) -> VariantSetup {
+        VariantSetup::try_read(buf).expect("valid binary fen")
+    }
+
+    pub fn try_read<B: Buf>(buf: &mut B) -> Result<VariantSetup, BinaryFenError> {
         let mut setup = Setup::empty();
 
         #[rustfmt::skip]
-        let mut unpack_piece = |sq: Square, packed: u8| {
+        let mut unpack_piece = |sq: Square, packed: u8| -> Result<(), BinaryFenError> {
             setup.board.set_piece_at(
                 sq,
                 match packed {
@@ -207,22 +256,23 @@ impl VariantSetup {
                         setup.turn = Color::Black;
                         Piece { color: Color::Black, role: Role::King }
                     }
-                    _ => panic!("invalid packed piece: {packed} at {sq}"),
+                    nibble => return Err(BinaryFenError::InvalidNibble { nibble }),
                 },
             );
+            Ok(())
         };
 
-        let mut occupied_iter = Bitboard(buf.get_u64()).into_iter();
+        let mut occupied_iter = Bitboard(read_u64(buf)?).into_iter();
         while let Some(sq) = occupied_iter.next() {
-            let (lo, hi) = read_nibbles(buf);
-            unpack_piece(sq, lo);
+            let (lo, hi) = read_nibbles(buf)?;
+            unpack_piece(sq, lo)?;
             if let Some(sq) = occupied_iter.next() {
-                unpack_piece(sq, hi);
+                unpack_piece(sq, hi)?;
             }
         }
 
-        setup.halfmoves = read_leb128(buf);
-        let ply = read_leb128(buf);
+        setup.halfmoves = read_leb128(buf)?;
+        let ply = read_leb128(buf)?;
         let variant = match read_byte(buf) {
             0 => Variant::Chess,
             1 => Variant::Crazyhouse,
@@ -232,29 +282,37 @@ impl VariantSetup {
             7 => Variant::Atomic,
             8 => Variant::Horde,
             9 => Variant::RacingKings,
-            n => panic!("invalid variant header: {n}"),
+            header => return Err(BinaryFenError::InvalidVariant { header }),
         };
 
         if ply % 2 == 1 {
             setup.turn = Color::Black;
         }
 
-        setup.fullmoves = NonZeroU32::new(1 + ply / 2).expect("fullmoves");
+        setup.fullmoves = NonZeroU32::MIN.saturating_add(ply / 2);
+
+        let remaining_checks = |nibble: u8| {
+            if nibble <= 3 {
+                Ok(RemainingChecks::new(nibble.into()))
+            } else {
+                Err(BinaryFenError::InvalidNibble { nibble })
+            }
+        };
 
         match variant {
             Variant::ThreeCheck => {
-                let (lo, hi) = read_nibbles(buf);
+                let (lo, hi) = read_nibbles(buf)?;
                 setup.remaining_checks = Some(ByColor {
-                    white: RemainingChecks::new(lo.into()),
-                    black: RemainingChecks::new(hi.into()),
+                    white: remaining_checks(lo)?,
+                    black: remaining_checks(hi)?,
                 });
             }
             Variant::Crazyhouse => {
-                let (wp, bp) = read_nibbles(buf);
-                let (wn, bn) = read_nibbles(buf);
-                let (wb, bb) = read_nibbles(buf);
-                let (wr, br) = read_nibbles(buf);
-                let (wq, bq) = read_nibbles(buf);
+                let (wp, bp) = read_nibbles(buf)?;
+                let (wn, bn) = read_nibbles(buf)?;
+                let (wb, bb) = read_nibbles(buf)?;
+                let (wr, br) = read_nibbles(buf)?;
+                let (wq, bq) = read_nibbles(buf)?;
                 setup.pockets = Some(ByColor {
                     white: ByRole {
                         pawn: wp,
@@ -273,10 +331,17 @@ impl VariantSetup {
                         king: 0,
                     },
                 });
+                if buf.has_remaining() {
+                    setup.promoted = Bitboard(read_u64(buf)?);
+                }
             }
             _ => {}
         }
 
-        VariantSetup { setup, variant }
+        if buf.has_remaining() {
+            return Err(BinaryFenError::TrailingBytes);
+        }
+
+        Ok(VariantSetup { setup, variant })
     }
 }
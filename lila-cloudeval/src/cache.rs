@@ -0,0 +1,248 @@
+use std::{
+    collections::HashMap,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard,
+    },
+};
+
+use lru::LruCache;
+use shakmaty::zobrist::Zobrist64;
+use tokio::sync::watch;
+
+use crate::database::Analysis;
+
+/// Analysis of a position with a number of pvs, each with at most
+/// `max_plies`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CacheKey {
+    pub hash: Zobrist64,
+    pub multi_pv: usize,
+    pub max_plies: usize,
+}
+
+#[derive(Debug)]
+struct Entry {
+    multi_pv: usize,
+    /// Longest pvs that can be answered, or `usize::MAX` if no pv was
+    /// truncated.
+    max_plies: usize,
+    analysis: Option<Analysis>,
+}
+
+impl Entry {
+    /// Answers a request, if possible. The pvs for fewer lines are always a
+    /// prefix of the pvs for more lines, and shorter pvs are a prefix of
+    /// longer pvs.
+    fn answer(&self, key: CacheKey) -> Option<Option<Analysis>> {
+        match self.analysis {
+            Some(ref analysis)
+                if key.multi_pv <= self.multi_pv && key.max_plies <= self.max_plies =>
+            {
+                Some(Some(Analysis {
+                    pvs: analysis
+                        .pvs
+                        .iter()
+                        .take(key.multi_pv)
+                        .map(|pv| {
+                            let mut pv = pv.clone();
+                            pv.truncate(key.max_plies);
+                            pv
+                        })
+                        .collect(),
+                    ply_from_root: analysis.ply_from_root,
+                }))
+            }
+            None if key.multi_pv >= self.multi_pv => Some(None),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct InFlight {
+    multi_pv: usize,
+    max_plies: usize,
+    id: u64,
+    /// Dropped once the analysis is no longer in flight, which wakes all
+    /// waiters.
+    done: watch::Sender<()>,
+}
+
+#[derive(Debug)]
+struct State {
+    entries: LruCache<Zobrist64, Entry>,
+    /// Positions that are currently being analyzed.
+    in_flight: HashMap<Zobrist64, InFlight>,
+    next_id: u64,
+}
+
+/// Outcome of a cache lookup.
+#[derive(Debug)]
+pub enum Lookup {
+    /// Answered from the cache.
+    Hit(Option<Analysis>),
+    /// Not cached. The caller is now responsible for the analysis. It should
+    /// store the result with [`AnalysisCache::insert()`], and must release
+    /// the claim with [`AnalysisCache::release()`] in any case.
+    Claimed(Claim),
+    /// Not cached, and another request is already analyzing the position.
+    /// The caller should analyze it without a claim.
+    Miss,
+}
+
+/// Responsibility for analyzing a position, see [`Lookup::Claimed`].
+#[derive(Debug)]
+#[must_use]
+pub struct Claim {
+    hash: Zobrist64,
+    id: u64,
+}
+
+/// Analysis that is in flight, see [`AnalysisCache::flight()`].
+#[derive(Debug)]
+pub struct Flight {
+    done: watch::Receiver<()>,
+}
+
+impl Flight {
+    /// Waits until the analysis is no longer in flight. It may have been
+    /// stored or given up.
+    pub async fn wait(mut self) {
+        // Nothing is ever sent. The sender is only dropped.
+        let _ = self.done.changed().await;
+    }
+}
+
+/// In-process cache of analysis for popular positions, keyed by Zobrist
+/// hash. Concurrent requests for the same position can share a single
+/// computation, by waiting for the analysis in flight before looking up
+/// the position.
+///
+/// Entries are never invalidated, so the cache is only suitable for a
+/// database that is not written to.
+#[derive(Debug)]
+pub struct AnalysisCache {
+    state: Mutex<State>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl AnalysisCache {
+    pub fn new(capacity: NonZeroUsize) -> AnalysisCache {
+        AnalysisCache {
+            state: Mutex::new(State {
+                entries: LruCache::new(capacity),
+                in_flight: HashMap::new(),
+                next_id: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("analysis cache lock")
+    }
+
+    /// Gets the analysis in flight that will be able to answer the request,
+    /// if any.
+    pub fn flight(&self, key: CacheKey) -> Option<Flight> {
+        self.lock()
+            .in_flight
+            .get(&key.hash)
+            .filter(|in_flight| {
+                in_flight.multi_pv >= key.multi_pv && in_flight.max_plies >= key.max_plies
+            })
+            .map(|in_flight| Flight {
+                done: in_flight.done.subscribe(),
+            })
+    }
+
+    pub fn lookup(&self, key: CacheKey) -> Lookup {
+        let mut state = self.lock();
+        if let Some(answer) = state
+            .entries
+            .get(&key.hash)
+            .and_then(|entry| entry.answer(key))
+        {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Lookup::Hit(answer);
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        if state.in_flight.contains_key(&key.hash) {
+            return Lookup::Miss;
+        }
+        let id = state.next_id;
+        state.next_id += 1;
+        state.in_flight.insert(
+            key.hash,
+            InFlight {
+                multi_pv: key.multi_pv,
+                max_plies: key.max_plies,
+                id,
+                done: watch::Sender::new(()),
+            },
+        );
+        Lookup::Claimed(Claim { hash: key.hash, id })
+    }
+
+    /// Stores the result of an analysis, with or without a claim. Pvs that
+    /// were cut short by anything other than `max_plies`, like a deadline,
+    /// depend on timing and are not stored.
+    pub fn insert(&self, key: CacheKey, analysis: &Option<Analysis>) {
+        let max_plies = match analysis {
+            Some(analysis) if !analysis.is_limited_by_plies(key.max_plies) => return,
+            Some(analysis) if analysis.pvs.iter().any(|pv| pv.truncated) => key.max_plies,
+            _ => usize::MAX,
+        };
+
+        let mut state = self.lock();
+        // Keep an existing entry if it is at least as useful.
+        if state
+            .entries
+            .peek(&key.hash)
+            .is_none_or(|entry| entry.answer(key).is_none())
+        {
+            state.entries.put(
+                key.hash,
+                Entry {
+                    multi_pv: key.multi_pv,
+                    max_plies,
+                    analysis: analysis.clone(),
+                },
+            );
+        }
+    }
+
+    /// Releases a claim, after the result was stored or the analysis was
+    /// given up. Wakes all waiters.
+    pub fn release(&self, claim: Claim) {
+        let mut state = self.lock();
+        if state
+            .in_flight
+            .get(&claim.hash)
+            .is_some_and(|in_flight| in_flight.id == claim.id)
+        {
+            state.in_flight.remove(&claim.hash);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
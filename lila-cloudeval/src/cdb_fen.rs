@@ -1,6 +1,7 @@
 use std::ops::Deref;
 
-use shakmaty::{Color, File, Piece, Rank, Role, Setup};
+use shakmaty::{CastlingSide, Color, File, Piece, Rank, Role, Setup, Square};
+use thiserror::Error;
 
 #[derive(Default, Debug)]
 pub struct Nibbles {
@@ -108,6 +109,25 @@ fn push_piece(nibbles: &mut Nibbles, piece: Piece) {
     });
 }
 
+#[rustfmt::skip]
+fn piece_from_nibble(nibble: u8) -> Option<Piece> {
+    Some(match nibble {
+        0x3 => Piece { color: Color::Black, role: Role::Pawn },
+        0x4 => Piece { color: Color::Black, role: Role::Knight },
+        0x5 => Piece { color: Color::Black, role: Role::Bishop },
+        0x6 => Piece { color: Color::Black, role: Role::Rook },
+        0x7 => Piece { color: Color::Black, role: Role::Queen },
+        0x9 => Piece { color: Color::Black, role: Role::King },
+        0xa => Piece { color: Color::White, role: Role::Pawn },
+        0xb => Piece { color: Color::White, role: Role::Knight },
+        0xc => Piece { color: Color::White, role: Role::Bishop },
+        0xd => Piece { color: Color::White, role: Role::Rook },
+        0xe => Piece { color: Color::White, role: Role::Queen },
+        0xf => Piece { color: Color::White, role: Role::King },
+        _ => return None,
+    })
+}
+
 fn push_cdb_fen(nibbles: &mut Nibbles, setup: &Setup) {
     // Prefix
     nibbles.push_byte(b'h');
@@ -189,3 +209,162 @@ pub fn cdb_fen(setup: &Setup) -> (Nibbles, NaturalOrder) {
         (nibbles_mirrored, NaturalOrder::Mirror)
     }
 }
+
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum CdbFenError {
+    #[error("missing prefix in cdb fen")]
+    InvalidPrefix,
+    #[error("unexpected end of cdb fen")]
+    Truncated,
+    #[error("invalid board in cdb fen")]
+    InvalidBoard,
+    #[error("invalid turn in cdb fen")]
+    InvalidTurn,
+    #[error("invalid castling rights in cdb fen")]
+    InvalidCastling,
+    #[error("invalid en passant square in cdb fen")]
+    InvalidEpSquare,
+    #[error("trailing nibbles after cdb fen")]
+    TrailingNibbles,
+}
+
+struct NibbleReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl NibbleReader<'_> {
+    fn remaining(&self) -> usize {
+        2 * self.bytes.len() - self.pos
+    }
+
+    fn peek(&self) -> Option<u8> {
+        let byte = self.bytes.get(self.pos / 2)?;
+        Some(match self.pos % 2 {
+            0 => byte >> 4,
+            _ => byte & 0xf,
+        })
+    }
+
+    fn next(&mut self) -> Result<u8, CdbFenError> {
+        let nibble = self.peek().ok_or(CdbFenError::Truncated)?;
+        self.pos += 1;
+        Ok(nibble)
+    }
+}
+
+fn castling_rook(setup: &Setup, color: Color, side: CastlingSide) -> Option<Square> {
+    let king = setup
+        .board
+        .king_of(color)
+        .filter(|k| k.rank() == color.backrank())?;
+
+    let candidates = setup.board.by_piece(color.rook()) & color.backrank();
+
+    match side {
+        CastlingSide::QueenSide => candidates.first().filter(|&rook| rook < king),
+        CastlingSide::KingSide => candidates.last().filter(|&rook| king < rook),
+    }
+}
+
+/// Decodes a key produced by [`cdb_fen()`].
+///
+/// The key of a position is shared with its mirrored counterpart, so the
+/// result may be the mirrored version of the originally encoded position.
+/// Move counters are not part of the key, and are set to their defaults.
+pub fn parse_cdb_fen(key: &[u8]) -> Result<Setup, CdbFenError> {
+    let Some((b'h', rest)) = key.split_first() else {
+        return Err(CdbFenError::InvalidPrefix);
+    };
+
+    let mut reader = NibbleReader {
+        bytes: rest,
+        pos: 0,
+    };
+
+    let mut setup = Setup::empty();
+
+    // Board
+    for rank in Rank::ALL.into_iter().rev() {
+        let mut file = 0;
+        while file < 8 {
+            match reader.next()? {
+                empty @ 0x0..=0x2 => file += u32::from(empty) + 1,
+                0x8 => match reader.next()? {
+                    empty @ 0x0..=0x4 => file += u32::from(empty) + 4,
+                    _ => return Err(CdbFenError::InvalidBoard),
+                },
+                nibble => {
+                    let piece = piece_from_nibble(nibble).ok_or(CdbFenError::InvalidBoard)?;
+                    setup
+                        .board
+                        .set_piece_at(Square::from_coords(File::new(file), rank), piece);
+                    file += 1;
+                }
+            }
+        }
+        if file > 8 {
+            return Err(CdbFenError::InvalidBoard);
+        }
+    }
+
+    // Turn
+    setup.turn = match reader.next()? {
+        0x0 => Color::White,
+        0x1 => Color::Black,
+        _ => return Err(CdbFenError::InvalidTurn),
+    };
+
+    // Castling rights
+    if reader.peek() == Some(0x0) {
+        reader.next()?;
+        if reader.next()? != 0x9 {
+            return Err(CdbFenError::InvalidCastling);
+        }
+    } else {
+        loop {
+            let rook = match reader.next()? {
+                0x9 => break, // Delimiter
+                0xa => castling_rook(&setup, Color::White, CastlingSide::KingSide),
+                0xb => castling_rook(&setup, Color::White, CastlingSide::QueenSide),
+                0xc => castling_rook(&setup, Color::Black, CastlingSide::KingSide),
+                0xd => castling_rook(&setup, Color::Black, CastlingSide::QueenSide),
+                0xe => match reader.next()? {
+                    file @ 0x1..=0x8 => Some(Square::from_coords(
+                        File::new(u32::from(file - 1)),
+                        Rank::First,
+                    )),
+                    _ => None,
+                },
+                file @ 0x1..=0x8 => Some(Square::from_coords(
+                    File::new(u32::from(file - 1)),
+                    Rank::Eighth,
+                )),
+                _ => None,
+            };
+            setup
+                .castling_rights
+                .add(rook.ok_or(CdbFenError::InvalidCastling)?);
+        }
+    }
+
+    // Ep square
+    if reader.remaining() >= 2 {
+        let file = reader.next()?;
+        let rank = reader.next()?;
+        if !(0x1..=0x8).contains(&file) || !(0x1..=0x8).contains(&rank) {
+            return Err(CdbFenError::InvalidEpSquare);
+        }
+        setup.ep_square = Some(Square::from_coords(
+            File::new(u32::from(file - 1)),
+            Rank::new(u32::from(rank - 1)),
+        ));
+    }
+
+    // Padding
+    match reader.remaining() {
+        0 => Ok(setup),
+        1 if reader.peek() == Some(0x0) => Ok(setup),
+        _ => Err(CdbFenError::TrailingNibbles),
+    }
+}
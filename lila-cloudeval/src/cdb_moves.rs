@@ -1,11 +1,12 @@
 use std::cmp::Reverse;
 
-use bytes::Buf;
+use bytes::{Buf, BufMut};
 use shakmaty::{uci::UciMove, File, Rank, Role, Square};
+use thiserror::Error;
 use File::*;
 use Rank::*;
 
-use crate::cdb_fen::NaturalOrder;
+use crate::{cdb_fen::NaturalOrder, score::Score};
 
 #[rustfmt::skip]
 const DEC_FILE: [Option<File>; 90] = [
@@ -35,17 +36,85 @@ const DEC_RANK: [Option<Rank>; 90] = [
     None,          None,          None,          None,          None,          None,          None,          None,          None,
 ];
 
+/// Error when decoding a chessdb.cn value. The offset points to the start of
+/// the offending record.
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum CdbDecodeError {
+    #[error("truncated move record at offset {offset}")]
+    Truncated { offset: usize },
+    #[error("invalid square index {index} at offset {offset}")]
+    InvalidSquare { offset: usize, index: usize },
+    #[error("invalid promotion at offset {offset}")]
+    InvalidPromotion { offset: usize },
+    #[error("invalid score {score} at offset {offset}")]
+    InvalidScore { offset: usize, score: i16 },
+    #[error("invalid ply from root {score} at offset {offset}")]
+    InvalidPlyFromRoot { offset: usize, score: i16 },
+    #[error("illegal move {uci}")]
+    IllegalMove { uci: UciMove },
+}
+
+/// Error when encoding a chessdb.cn value.
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum CdbEncodeError {
+    #[error("move not representable in chessdb move record: {uci}")]
+    UnsupportedMove { uci: UciMove },
+    #[error("ply from root out of range: {ply}")]
+    PlyFromRootRange { ply: u32 },
+}
+
+fn enc_square(file: File, rank_row: u8) -> u8 {
+    rank_row * 9 + u8::from(file)
+}
+
+fn enc_move(uci: &UciMove) -> Option<(u8, u8)> {
+    let UciMove::Normal {
+        from,
+        to,
+        promotion,
+    } = *uci
+    else {
+        return None;
+    };
+
+    let src = enc_square(from.file(), u8::from(from.rank()) + 1);
+    let dst = match promotion {
+        None => enc_square(to.file(), u8::from(to.rank()) + 1),
+        Some(role) => {
+            // The target rank is implied by the source rank, so the rank
+            // field is reused for the promotion role.
+            match (from.rank(), to.rank()) {
+                (Rank::Seventh, Rank::Eighth) | (Rank::Second, Rank::First) => (),
+                _ => return None,
+            }
+            0x80 | enc_square(
+                to.file(),
+                match role {
+                    Role::Queen => 0,
+                    Role::Rook => 1,
+                    Role::Bishop => 2,
+                    Role::Knight => 3,
+                    _ => return None,
+                },
+            )
+        }
+    };
+
+    Some((dst, src))
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
-pub struct RelativeScore(pub i16);
+pub struct RelativeScore(pub Score);
 
 fn good_move_threshold(RelativeScore(max_score): RelativeScore) -> RelativeScore {
-    RelativeScore(if max_score >= 50 {
+    let max_score = max_score.to_cdb();
+    RelativeScore(Score::from_cdb(if max_score >= 50 {
         max_score - 1
     } else if max_score >= -30 {
         (f64::from(max_score) - 10.0 / (1.0 + (-f64::from(max_score).abs() / 10.0).exp())) as i16
     } else {
         -50
-    })
+    }))
 }
 
 #[derive(Debug)]
@@ -84,19 +153,33 @@ impl ScoredMoves {
         self.ply_from_root
     }
 
+    pub fn set_ply_from_root(&mut self, ply_from_root: Option<u32>) {
+        self.ply_from_root = ply_from_root;
+    }
+
     pub fn moves(&self) -> &[ScoredMove] {
         &self.moves
     }
 
-    pub fn num_good_moves(&self) -> usize {
+    pub fn push(&mut self, scored_move: ScoredMove) {
+        self.moves.push(scored_move);
+    }
+
+    /// Minimum score of moves that are considered good, relative to the
+    /// best move.
+    pub fn good_move_threshold(&self) -> RelativeScore {
         let maximum_score = self
             .moves
             .iter()
             .map(|e| e.score)
             .max()
-            .unwrap_or(RelativeScore(0));
+            .unwrap_or(RelativeScore(Score::Cp(0)));
 
-        let threshold = good_move_threshold(maximum_score);
+        good_move_threshold(maximum_score)
+    }
+
+    pub fn num_good_moves(&self) -> usize {
+        let threshold = self.good_move_threshold();
 
         self.moves
             .iter()
@@ -114,31 +197,85 @@ impl ScoredMoves {
         SortedScoredMoves(self)
     }
 
+    /// Reads scored moves from a chessdb.cn value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is invalid. Use [`ScoredMoves::try_read_cdb()`]
+    /// for untrusted input.
     pub fn read_cdb<B: Buf>(buf: &mut B, natural_order: NaturalOrder) -> ScoredMoves {
+        ScoredMoves::try_read_cdb(buf, natural_order).expect("valid chessdb move record")
+    }
+
+    pub fn try_read_cdb<B: Buf>(
+        buf: &mut B,
+        natural_order: NaturalOrder,
+    ) -> Result<ScoredMoves, CdbDecodeError> {
         let mut res = ScoredMoves::with_capacity(buf.remaining() / 4);
-        res.extend_from_cdb(buf, natural_order);
-        res
+        res.try_extend_from_cdb(buf, natural_order)?;
+        Ok(res)
     }
 
+    /// Extends with scored moves from a chessdb.cn value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is invalid. Use
+    /// [`ScoredMoves::try_extend_from_cdb()`] for untrusted input.
     pub fn extend_from_cdb<B: Buf>(&mut self, buf: &mut B, natural_order: NaturalOrder) {
+        self.try_extend_from_cdb(buf, natural_order)
+            .expect("valid chessdb move record");
+    }
+
+    /// Extends with scored moves from a chessdb.cn value. On error, moves
+    /// decoded before the offending record are kept.
+    pub fn try_extend_from_cdb<B: Buf>(
+        &mut self,
+        buf: &mut B,
+        natural_order: NaturalOrder,
+    ) -> Result<(), CdbDecodeError> {
+        let len = buf.remaining();
+
         while buf.has_remaining() {
+            let offset = len - buf.remaining();
+
+            if buf.remaining() < 4 {
+                return Err(CdbDecodeError::Truncated { offset });
+            }
+
             let dst = usize::from(buf.get_u8());
             let src = usize::from(buf.get_u8());
             let score = buf.get_i16_le();
 
             if src == 0 && dst == 0 {
-                self.ply_from_root = Some(u32::try_from(score).unwrap());
+                self.ply_from_root = Some(
+                    u32::try_from(score)
+                        .map_err(|_| CdbDecodeError::InvalidPlyFromRoot { offset, score })?,
+                );
                 continue;
             }
 
-            let from = Square::from_coords(DEC_FILE[src].unwrap(), DEC_RANK[src].unwrap());
-            let to_file = DEC_FILE[dst & 0x7f].unwrap();
+            let from = DEC_FILE
+                .get(src)
+                .copied()
+                .flatten()
+                .zip(DEC_RANK.get(src).copied().flatten())
+                .map(|(file, rank)| Square::from_coords(file, rank))
+                .ok_or(CdbDecodeError::InvalidSquare { offset, index: src })?;
+            let to_file = DEC_FILE
+                .get(dst & 0x7f)
+                .copied()
+                .flatten()
+                .ok_or(CdbDecodeError::InvalidSquare { offset, index: dst })?;
             let to_rank = DEC_RANK[dst & 0x7f];
 
             let uci = if dst & 0x80 == 0 {
                 UciMove::Normal {
                     from,
-                    to: Square::from_coords(to_file, to_rank.unwrap()),
+                    to: Square::from_coords(
+                        to_file,
+                        to_rank.ok_or(CdbDecodeError::InvalidSquare { offset, index: dst })?,
+                    ),
                     promotion: None,
                 }
             } else {
@@ -149,7 +286,7 @@ impl ScoredMoves {
                         match from.rank() {
                             Rank::Seventh => Rank::Eighth,
                             Rank::Second => Rank::First,
-                            r => panic!("invalid rank for promotion: {r}"),
+                            _ => return Err(CdbDecodeError::InvalidPromotion { offset }),
                         },
                     ),
                     promotion: Some(match to_rank {
@@ -157,19 +294,68 @@ impl ScoredMoves {
                         Some(Rank::First) => Role::Rook,
                         Some(Rank::Second) => Role::Bishop,
                         Some(Rank::Third) => Role::Knight,
-                        r => panic!("invalid rank to encode promotion role: {r:?}"),
+                        _ => return Err(CdbDecodeError::InvalidPromotion { offset }),
                     }),
                 }
             };
 
+            if score == i16::MIN || score.abs() > Score::MAX.to_cdb() {
+                return Err(CdbDecodeError::InvalidScore { offset, score });
+            }
+
             self.moves.push(ScoredMove {
                 uci: match natural_order {
                     NaturalOrder::Same => uci,
                     NaturalOrder::Mirror => uci.to_mirrored(),
                 },
-                score: RelativeScore(score.checked_neg().expect("negated score")),
+                score: RelativeScore(-Score::from_cdb(score)),
             });
         }
+
+        Ok(())
+    }
+
+    /// Writes scored moves as a chessdb.cn value, the inverse of
+    /// [`ScoredMoves::try_read_cdb()`]. The ply from root entry, if any, is
+    /// written first. Nothing is written on error.
+    pub fn write_cdb<B: BufMut>(
+        &self,
+        buf: &mut B,
+        natural_order: NaturalOrder,
+    ) -> Result<(), CdbEncodeError> {
+        let ply_from_root = self
+            .ply_from_root
+            .map(|ply| i16::try_from(ply).map_err(|_| CdbEncodeError::PlyFromRootRange { ply }))
+            .transpose()?;
+
+        let records = self
+            .moves
+            .iter()
+            .map(|entry| {
+                let uci = match natural_order {
+                    NaturalOrder::Same => entry.uci.clone(),
+                    NaturalOrder::Mirror => entry.uci.to_mirrored(),
+                };
+                let (dst, src) = enc_move(&uci).ok_or(CdbEncodeError::UnsupportedMove {
+                    uci: entry.uci.clone(),
+                })?;
+                Ok((dst, src, (-entry.score.0).to_cdb_exact()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if let Some(ply_from_root) = ply_from_root {
+            buf.put_u8(0);
+            buf.put_u8(0);
+            buf.put_i16_le(ply_from_root);
+        }
+
+        for (dst, src, score) in records {
+            buf.put_u8(dst);
+            buf.put_u8(src);
+            buf.put_i16_le(score);
+        }
+
+        Ok(())
     }
 }
 
@@ -184,6 +370,10 @@ impl SortedScoredMoves {
         self.0.moves()
     }
 
+    pub fn good_move_threshold(&self) -> RelativeScore {
+        self.0.good_move_threshold()
+    }
+
     pub fn into_moves(self) -> Vec<ScoredMove> {
         self.0.moves
     }
@@ -203,7 +393,7 @@ impl SortedScoredMoves {
             let min_score = self
                 .moves()
                 .get(at_least - 1)
-                .map_or(RelativeScore(i16::MIN), |entry| entry.score);
+                .map_or(RelativeScore(Score::MIN), |entry| entry.score);
 
             self.0
                 .moves
@@ -1,18 +1,36 @@
-use std::{cmp::Reverse, collections::HashSet, path::PathBuf, sync::Arc};
+use std::{
+    cmp::Reverse,
+    collections::HashSet,
+    num::NonZeroUsize,
+    ops::Deref,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_with::{formats::SpaceSeparator, serde_as, StringWithSeparator};
 use shakmaty::{
     uci::UciMove,
+    variant::{Variant, VariantPosition},
     zobrist::{Zobrist64, ZobristHash},
-    Chess, Color, EnPassantMode, Position, Setup,
+    Chess, Color, EnPassantMode, Move, Position, Setup,
+};
+use terarkdb::{
+    BlockBasedTableOptions, Cache, Db, Error as DbError, LogFile, Options, ReadOptions,
 };
-use terarkdb::{BlockBasedTableOptions, Cache, Db, Error as DbError, LogFile, Options};
-use tokio::{task, task::JoinHandle};
 
 use crate::{
-    cdb_fen::cdb_fen,
-    cdb_moves::{RelativeScore, ScoredMoves, SortedScoredMoves},
+    cache::{AnalysisCache, CacheKey, Claim, Lookup},
+    cdb_fen::{cdb_fen, NaturalOrder, Nibbles},
+    cdb_moves::{CdbDecodeError, RelativeScore, ScoredMoves, SortedScoredMoves},
+    error::Error,
+    eval::{Eval, EvalQuality},
+    lookup_pool::{LookupPool, Overloaded},
+    metrics::METRICS,
+    store::{PositionStore, TerarkdbStore},
+    tablebase::Tablebases,
+    user_eval::{user_eval_key, UserEval},
 };
 
 #[derive(Debug, clap::Parser)]
@@ -21,6 +39,25 @@ pub struct DatabaseOpt {
     db_path: PathBuf,
     #[arg(long, default_value = "104857600")] // 100 MiB
     db_block_cache_bytes: usize,
+    /// Writable database for user provided evals. Created if missing.
+    #[arg(long)]
+    user_eval_db_path: Option<PathBuf>,
+    /// Directory with Syzygy tablebases for standard chess. Can be given
+    /// multiple times.
+    #[arg(long)]
+    syzygy_path: Vec<PathBuf>,
+    /// Number of positions to keep in the in-process analysis cache. 0
+    /// disables the cache.
+    #[arg(long, default_value = "100000")]
+    analysis_cache_entries: usize,
+    /// Number of threads for database lookups, which is also the maximum
+    /// number of lookups in flight.
+    #[arg(long, default_value = "16")]
+    lookup_threads: NonZeroUsize,
+    /// Maximum number of lookups waiting for a free thread. Requests beyond
+    /// that are rejected as overloaded.
+    #[arg(long, default_value = "256")]
+    lookup_queue: usize,
 }
 
 impl DatabaseOpt {
@@ -34,23 +71,163 @@ impl DatabaseOpt {
             );
         options
     }
+
+    fn open_user_evals(&self) -> Result<Option<Db>, DbError> {
+        self.user_eval_db_path
+            .as_ref()
+            .map(|path| {
+                let mut options = Options::default();
+                options.set_create_if_missing(true);
+                Db::open(&options, path)
+            })
+            .transpose()
+    }
+
+    fn open_tablebases(&self) -> Result<Option<Tablebases>, Error> {
+        if self.syzygy_path.is_empty() {
+            return Ok(None);
+        }
+        Tablebases::open(&self.syzygy_path)
+            .map(Some)
+            .map_err(Error::OpenTablebases)
+    }
+
+    fn lookup_pool(&self) -> LookupPool {
+        LookupPool::new(self.lookup_threads, self.lookup_queue)
+    }
+
+    fn analysis_cache(&self) -> Option<AnalysisCache> {
+        NonZeroUsize::new(self.analysis_cache_entries).map(AnalysisCache::new)
+    }
 }
 
-#[derive(Serialize)]
-pub struct WhiteScore(pub i16);
+/// Score from the point of view of white, as expected by lila.
+///
+/// Tablebase results without known distance to mate are reported as large
+/// centipawn values, just like chessdb.cn does.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WhiteScore {
+    Cp(i16),
+    Mate(i32),
+}
 
 impl WhiteScore {
-    fn from_relative(RelativeScore(cp): RelativeScore, turn: Color) -> WhiteScore {
-        WhiteScore(turn.fold_wb(cp, -cp))
+    pub(crate) fn from_relative(RelativeScore(score): RelativeScore, turn: Color) -> WhiteScore {
+        let score = turn.fold_wb(score, -score);
+        match score.mate_moves() {
+            Some(moves) => WhiteScore::Mate(moves),
+            None => WhiteScore::Cp(score.to_cdb()),
+        }
     }
 }
 
 #[serde_as]
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub struct Pv {
-    cp: WhiteScore,
+    #[serde(flatten)]
+    pub score: WhiteScore,
     #[serde_as(as = "StringWithSeparator::<SpaceSeparator, UciMove>")]
-    moves: Vec<UciMove>,
+    pub moves: Vec<UciMove>,
+    /// Whether the pv was cut short, rather than ending at a missing or
+    /// repeated position. Never taken from clients.
+    #[serde(skip_deserializing, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+impl Pv {
+    /// Cuts the pv to at most `max_plies`.
+    pub fn truncate(&mut self, max_plies: usize) {
+        if self.moves.len() > max_plies {
+            self.moves.truncate(max_plies);
+            self.truncated = true;
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Analysis {
+    pub pvs: Vec<Pv>,
+    pub ply_from_root: Option<u32>,
+}
+
+impl Analysis {
+    /// Whether pvs were only cut short by `max_plies`, if at all. A pv that
+    /// hits another limit of the budget at exactly `max_plies` is the same
+    /// as one cut short by `max_plies`.
+    pub(crate) fn is_limited_by_plies(&self, max_plies: usize) -> bool {
+        self.pvs
+            .iter()
+            .all(|pv| !pv.truncated || pv.moves.len() >= max_plies)
+    }
+}
+
+/// Limits on the work spent extending pvs. Pvs that are cut short are
+/// marked as truncated.
+#[derive(Debug, Copy, Clone)]
+pub struct PvBudget {
+    /// Maximum number of plies in each pv.
+    pub max_plies: usize,
+    /// Maximum number of positions looked up while extending the pvs.
+    pub max_lookups: usize,
+    /// Stop extending the pvs once this point in time has passed.
+    pub deadline: Option<Instant>,
+    /// Stop extending the pvs once this much time has passed since
+    /// extending the pvs of the position began.
+    pub time_limit: Option<Duration>,
+}
+
+impl Default for PvBudget {
+    fn default() -> PvBudget {
+        PvBudget {
+            max_plies: usize::MAX,
+            max_lookups: usize::MAX,
+            deadline: None,
+            time_limit: None,
+        }
+    }
+}
+
+impl PvBudget {
+    /// Starts the time limit, if any, by turning it into a deadline.
+    pub(crate) fn start(&self) -> PvBudget {
+        PvBudget {
+            deadline: self
+                .deadline
+                .into_iter()
+                .chain(self.time_limit.map(|limit| Instant::now() + limit))
+                .min(),
+            time_limit: None,
+            ..*self
+        }
+    }
+
+    pub(crate) fn is_expired(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/// Request for the principal variations of a standard chess position.
+#[derive(Debug, Copy, Clone)]
+pub struct PvRequest<'a> {
+    pub pos: &'a Chess,
+    pub multi_pv: usize,
+    /// Hashes of earlier positions in the game. Pvs end when they repeat
+    /// one of them.
+    pub history: &'a [Zobrist64],
+    pub budget: PvBudget,
+}
+
+impl PvRequest<'_> {
+    pub fn new(pos: &Chess, multi_pv: usize) -> PvRequest<'_> {
+        PvRequest {
+            pos,
+            multi_pv,
+            history: &[],
+            budget: PvBudget::default(),
+        }
+    }
 }
 
 struct TiebrokenMove {
@@ -70,165 +247,796 @@ impl TiebrokenMove {
     }
 }
 
+struct MultiPvRoot {
+    moves: Vec<TiebrokenMove>,
+    ply_from_root: Option<u32>,
+}
+
+/// Request for the eval of a position.
+#[derive(Debug, Clone)]
+pub struct EvalRequest {
+    pub pos: VariantPosition,
+    pub multi_pv: usize,
+    /// Hashes of earlier positions in the game. Pvs end when they repeat
+    /// one of them.
+    pub history: Vec<Zobrist64>,
+    pub budget: PvBudget,
+}
+
+impl EvalRequest {
+    pub fn new(pos: VariantPosition, multi_pv: usize) -> EvalRequest {
+        EvalRequest {
+            pos,
+            multi_pv,
+            history: Vec::new(),
+            budget: PvBudget::default(),
+        }
+    }
+}
+
+/// How a chessdb.cn lookup uses the analysis cache.
+#[derive(Debug)]
+enum CacheSlot {
+    /// Bypass the cache.
+    Uncached,
+    /// Already answered from the cache.
+    Hit(Option<Analysis>),
+    /// Look up the position and store the result in the cache.
+    Store(CacheKey),
+}
+
+/// Claims on the analysis cache, released on drop. This also happens if
+/// the lookup fails or the request is cancelled.
+struct Claims<'a> {
+    cache: &'a AnalysisCache,
+    claims: Vec<Claim>,
+}
+
+impl Drop for Claims<'_> {
+    fn drop(&mut self) {
+        for claim in self.claims.drain(..) {
+            self.cache.release(claim);
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Database {
     inner: Db,
+    user_evals: Option<Db>,
+    tablebases: Option<Tablebases>,
+    analysis_cache: Option<AnalysisCache>,
+    lookup_pool: LookupPool,
 }
 
 impl Database {
-    pub fn open_blocking(opt: &DatabaseOpt) -> Result<Database, DbError> {
+    pub fn open_blocking(opt: &DatabaseOpt) -> Result<Database, Error> {
         Ok(Database {
             inner: Db::open(&opt.to_options(), &opt.db_path)?,
+            user_evals: opt.open_user_evals()?,
+            tablebases: opt.open_tablebases()?,
+            // The database may be written to, so results can not be cached.
+            analysis_cache: None,
+            lookup_pool: opt.lookup_pool(),
         })
     }
 
-    pub fn open_read_only_blocking(opt: &DatabaseOpt) -> Result<Database, DbError> {
+    /// Opens the chessdb.cn dump read-only. The user eval database, if any,
+    /// is still writable.
+    pub fn open_read_only_blocking(opt: &DatabaseOpt) -> Result<Database, Error> {
         Ok(Database {
             inner: Db::open_read_only(&opt.to_options(), &opt.db_path, LogFile::Ignore)?,
+            user_evals: opt.open_user_evals()?,
+            tablebases: opt.open_tablebases()?,
+            analysis_cache: opt.analysis_cache(),
+            lookup_pool: opt.lookup_pool(),
         })
     }
 
+    pub fn analysis_cache(&self) -> Option<&AnalysisCache> {
+        self.analysis_cache.as_ref()
+    }
+
+    /// Renders server metrics, including internals of the chessdb.cn dump.
+    pub fn render_metrics(&self) -> String {
+        METRICS.observe_db(&self.inner);
+        if let Some(cache) = &self.analysis_cache {
+            METRICS.observe_cache(cache);
+        }
+        METRICS.render()
+    }
+
+    /// Runs a blocking job on the lookup pool.
+    async fn run_blocking<F, T>(self: Arc<Self>, f: F) -> Result<T, Overloaded>
+    where
+        F: FnOnce(&Database) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let db = Arc::clone(&self);
+        self.lookup_pool.run(move || f(&db)).await
+    }
+
+    /// Key in the analysis cache, if the analysis can be cached. Requests
+    /// with history are not cached, because the history can cut pvs short.
+    /// Positions covered by the tablebases do not need the cache.
+    fn cache_key(&self, request: &PvRequest<'_>) -> Option<CacheKey> {
+        self.analysis_cache.as_ref()?;
+        if !request.history.is_empty()
+            || self
+                .tablebases
+                .as_ref()
+                .is_some_and(|tablebases| tablebases.covers(request.pos))
+        {
+            return None;
+        }
+        Some(CacheKey {
+            hash: request.pos.zobrist_hash(EnPassantMode::Legal),
+            multi_pv: request.multi_pv,
+            max_plies: request.budget.max_plies,
+        })
+    }
+
+    /// Looks up the analysis cache before submitting work to the lookup
+    /// pool. Analysis of the same positions that is already in flight is
+    /// awaited first, without holding a pool thread or any claims, so that
+    /// requests can not wait for each other.
+    async fn cache_slots(&self, keys: &[Option<CacheKey>]) -> (Vec<CacheSlot>, Option<Claims<'_>>) {
+        let Some(cache) = &self.analysis_cache else {
+            return (keys.iter().map(|_| CacheSlot::Uncached).collect(), None);
+        };
+
+        for &key in keys.iter().flatten() {
+            if let Some(flight) = cache.flight(key) {
+                flight.wait().await;
+            }
+        }
+
+        // If the analysis was given up, or another request started analyzing
+        // the position in the meantime, compute it in parallel rather than
+        // waiting again.
+        let mut claims = Claims {
+            cache,
+            claims: Vec::new(),
+        };
+        let slots = keys
+            .iter()
+            .map(|&key| {
+                let Some(key) = key else {
+                    return CacheSlot::Uncached;
+                };
+                match cache.lookup(key) {
+                    Lookup::Hit(analysis) => CacheSlot::Hit(analysis),
+                    Lookup::Claimed(claim) => {
+                        claims.claims.push(claim);
+                        CacheSlot::Store(key)
+                    }
+                    Lookup::Miss => CacheSlot::Store(key),
+                }
+            })
+            .collect();
+        (slots, Some(claims))
+    }
+
     pub async fn get_multi_pv(
         self: Arc<Self>,
         pos: Chess,
         multi_pv: usize,
-    ) -> Result<Option<Vec<Pv>>, DbError> {
-        let Some(root) = self.clone().multi_pv_root(pos.clone(), multi_pv).await? else {
-            return Ok(None);
-        };
+    ) -> Result<Option<Analysis>, Error> {
+        let db = Arc::clone(&self);
+        let key = db.cache_key(&PvRequest::new(&pos, multi_pv));
+        let (slots, claims) = db.cache_slots(&[key]).await;
+        let res = self
+            .run_blocking(move |db| {
+                db.get_multi_pvs_blocking(&[PvRequest::new(&pos, multi_pv)], slots)
+                    .pop()
+                    .expect("one analysis")
+            })
+            .await;
+        drop(claims);
+        res?
+    }
 
-        let db = &self;
-        let extend_pv_handles: Vec<JoinHandle<_>> = root
-            .into_iter()
-            .map(move |begin| task::spawn(db.clone().extend_pv(pos.clone(), begin)))
+    /// Gets the best available eval of a position, from the tablebases,
+    /// chessdb.cn or the user eval database.
+    pub async fn get_eval(self: Arc<Self>, request: EvalRequest) -> Result<Option<Eval>, Error> {
+        self.get_evals(vec![request]).await.pop().expect("one eval")
+    }
+
+    /// Like [`Database::get_eval()`], but bypassing the analysis cache.
+    fn get_eval_uncached_blocking(&self, request: EvalRequest) -> Result<Option<Eval>, Error> {
+        self.get_evals_blocking(&[request], vec![CacheSlot::Uncached])
+            .pop()
+            .expect("one eval")
+    }
+
+    /// Like [`Database::get_eval()`] for many positions at once. Root
+    /// positions and user evals are looked up with a single `multi_get`
+    /// each. If the lookup pool is overloaded, all requests fail.
+    pub async fn get_evals(
+        self: Arc<Self>,
+        requests: Vec<EvalRequest>,
+    ) -> Vec<Result<Option<Eval>, Error>> {
+        let n = requests.len();
+        let keys: Vec<_> = requests
+            .iter()
+            .map(|request| match request.pos {
+                VariantPosition::Chess(ref pos) => self.cache_key(&PvRequest {
+                    pos,
+                    multi_pv: request.multi_pv,
+                    history: &request.history,
+                    budget: request.budget,
+                }),
+                _ => None,
+            })
             .collect();
+        let db = Arc::clone(&self);
+        let (slots, claims) = db.cache_slots(&keys).await;
+        let res = self
+            .run_blocking(move |db| db.get_evals_blocking(&requests, slots))
+            .await
+            .unwrap_or_else(|Overloaded| (0..n).map(|_| Err(Error::Overloaded)).collect());
+        drop(claims);
+        res
+    }
+
+    fn get_evals_blocking(
+        &self,
+        requests: &[EvalRequest],
+        slots: Vec<CacheSlot>,
+    ) -> Vec<Result<Option<Eval>, Error>> {
+        let mut tablebase_evals: Vec<Option<Result<Option<Eval>, Error>>> =
+            requests.iter().map(|_| None).collect();
+        let mut analyses: Vec<Result<Option<Analysis>, Error>> =
+            requests.iter().map(|_| Ok(None)).collect();
+
+        // Tablebases and chessdb.cn only have standard chess. Other variants
+        // fall back to user evals.
+        let mut chessdb_indexes = Vec::new();
+        let mut chessdb_requests = Vec::new();
+        let mut chessdb_slots = Vec::new();
+        for (i, (request, slot)) in requests.iter().zip(slots).enumerate() {
+            let VariantPosition::Chess(pos) = &request.pos else {
+                continue;
+            };
+            if let Some(tablebases) = &self.tablebases {
+                match tablebases.get_multi_pv(pos, request.multi_pv, &request.budget) {
+                    Ok(Some(analysis)) => {
+                        tablebase_evals[i] = Some(Ok(Some(Eval::Tablebase(analysis))));
+                        continue;
+                    }
+                    Ok(None) => (),
+                    Err(err) => {
+                        tablebase_evals[i] = Some(Err(err));
+                        continue;
+                    }
+                }
+            }
+            chessdb_indexes.push(i);
+            chessdb_requests.push(PvRequest {
+                pos,
+                multi_pv: request.multi_pv,
+                history: &request.history,
+                budget: request.budget,
+            });
+            chessdb_slots.push(slot);
+        }
+        for (i, analysis) in chessdb_indexes
+            .into_iter()
+            .zip(self.get_multi_pvs_blocking(&chessdb_requests, chessdb_slots))
+        {
+            analyses[i] = analysis;
+        }
+        let user_evals = self.get_user_evals_blocking(
+            requests
+                .iter()
+                .map(|request| {
+                    user_eval_key(
+                        request.pos.clone().into_setup(EnPassantMode::Legal),
+                        request.pos.variant(),
+                    )
+                })
+                .collect(),
+        );
+
+        requests
+            .iter()
+            .zip(tablebase_evals)
+            .zip(analyses.into_iter().zip(user_evals))
+            .map(|((request, tablebase_eval), (analysis, user_eval))| {
+                let mut eval = match tablebase_eval {
+                    Some(tablebase_eval) => tablebase_eval?,
+                    None => Eval::choose(
+                        analysis?,
+                        user_eval?,
+                        request.multi_pv,
+                        request.pos.legal_moves().len(),
+                    ),
+                };
+                // Also applies to tablebase pvs, user evals and cached
+                // analysis.
+                if let Some(ref mut eval) = eval {
+                    eval.truncate_pvs(request.budget.max_plies);
+                }
+                Ok(eval)
+            })
+            .collect()
+    }
+
+    /// Gets the chessdb.cn analysis of many positions, using the slots
+    /// prepared by [`Database::cache_slots()`].
+    fn get_multi_pvs_blocking(
+        &self,
+        requests: &[PvRequest<'_>],
+        slots: Vec<CacheSlot>,
+    ) -> Vec<Result<Option<Analysis>, Error>> {
+        let mut results: Vec<Option<Result<Option<Analysis>, Error>>> =
+            Vec::with_capacity(requests.len());
+        let mut misses = Vec::new();
+        for (i, slot) in slots.into_iter().enumerate() {
+            match slot {
+                CacheSlot::Hit(analysis) => results.push(Some(Ok(analysis))),
+                CacheSlot::Uncached => {
+                    results.push(None);
+                    misses.push((i, None));
+                }
+                CacheSlot::Store(key) => {
+                    results.push(None);
+                    misses.push((i, Some(key)));
+                }
+            }
+        }
 
-        let mut result = Vec::with_capacity(extend_pv_handles.len());
-        for handle in extend_pv_handles {
-            result.push(handle.await.expect("join extend pv")?);
+        let miss_requests: Vec<_> = misses.iter().map(|&(i, _)| requests[i]).collect();
+        for ((i, key), result) in misses
+            .into_iter()
+            .zip(self.get_multi_pvs_uncached_blocking(&miss_requests))
+        {
+            if let (Some(cache), Some(key), Ok(analysis)) = (&self.analysis_cache, key, &result) {
+                cache.insert(key, analysis);
+            }
+            results[i] = Some(result);
         }
-        Ok(Some(result))
+
+        results
+            .into_iter()
+            .map(|result| result.expect("result for each request"))
+            .collect()
     }
 
-    async fn multi_pv_root(
+    fn get_multi_pvs_uncached_blocking(
+        &self,
+        requests: &[PvRequest<'_>],
+    ) -> Vec<Result<Option<Analysis>, Error>> {
+        if requests.is_empty() {
+            return Vec::new();
+        }
+
+        // Read all lookups from the same snapshot, so that the pvs are
+        // consistent even if the database is concurrently written to.
+        let snapshot = self.inner.snapshot();
+        let mut read_options = ReadOptions::new();
+        read_options.set_snapshot(&snapshot);
+
+        get_multi_pvs(
+            &TerarkdbStore::new(&self.inner, &read_options),
+            self.tablebases.as_ref(),
+            requests,
+        )
+    }
+
+    /// Gets all scored moves of a position from chessdb.cn, which only has
+    /// standard chess.
+    pub async fn query_all(
         self: Arc<Self>,
-        pos: Chess,
-        multi_pv: usize,
-    ) -> Result<Option<Vec<TiebrokenMove>>, DbError> {
-        task::spawn_blocking(move || self.multi_pv_root_blocking(&pos, multi_pv))
-            .await
-            .expect("multi pv root blocking")
+        pos: VariantPosition,
+    ) -> Result<Option<SortedScoredMoves>, Error> {
+        let VariantPosition::Chess(pos) = pos else {
+            return Ok(None);
+        };
+        self.run_blocking(move |db| db.get_blocking(pos.into_setup(EnPassantMode::Legal)))
+            .await?
+    }
+
+    pub fn get_blocking(&self, setup: Setup) -> Result<Option<SortedScoredMoves>, Error> {
+        get_scored_moves(
+            &TerarkdbStore::new(&self.inner, &ReadOptions::default()),
+            &setup,
+        )
+    }
+
+    fn get_user_evals_blocking(&self, keys: Vec<Vec<u8>>) -> Vec<Result<Option<UserEval>, Error>> {
+        let Some(user_evals) = &self.user_evals else {
+            return keys.iter().map(|_| Ok(None)).collect();
+        };
+
+        user_evals
+            .multi_get(&keys)
+            .into_iter()
+            .map(|row| match row? {
+                Some(value) => Ok(Some(UserEval::try_read(&mut &value[..])?)),
+                None => Ok(None),
+            })
+            .collect()
     }
 
-    fn multi_pv_root_blocking(
+    pub fn get_user_eval_blocking(
         &self,
-        pos: &Chess,
-        multi_pv: usize,
-    ) -> Result<Option<Vec<TiebrokenMove>>, DbError> {
-        let Some(root) = self.get_blocking(pos.clone().into_setup(EnPassantMode::Legal))? else {
-            return Ok(None); // Root position not found
+        setup: Setup,
+        variant: Variant,
+    ) -> Result<Option<UserEval>, Error> {
+        let Some(user_evals) = &self.user_evals else {
+            return Ok(None);
+        };
+
+        let Some(value) = user_evals.get_pinned(user_eval_key(setup, variant))? else {
+            return Ok(None);
         };
 
-        if root.len() < multi_pv && root.len() < pos.legal_moves().len() {
-            return Ok(None); // Cannot satisfy number of requested pvs
+        Ok(Some(UserEval::try_read(&mut &value[..])?))
+    }
+
+    /// Stores a validated user eval, unless an eval of at least the same
+    /// quality is already available. Returns whether the eval was stored.
+    /// The existing eval is looked up within `budget`.
+    pub async fn submit_user_eval(
+        self: Arc<Self>,
+        pos: VariantPosition,
+        user_eval: UserEval,
+        budget: PvBudget,
+    ) -> Result<bool, Error> {
+        self.run_blocking(move |db| db.submit_user_eval_blocking(&pos, &user_eval, budget))
+            .await?
+    }
+
+    fn submit_user_eval_blocking(
+        &self,
+        pos: &VariantPosition,
+        user_eval: &UserEval,
+        budget: PvBudget,
+    ) -> Result<bool, Error> {
+        if self.user_evals.is_none() {
+            return Err(Error::UserEvalsDisabled);
         }
 
-        let mut tiebroken_moves = self.tiebreak_moves_blocking(pos, root, multi_pv)?;
-        tiebroken_moves.sort_by_key(TiebrokenMove::sort_key);
-        tiebroken_moves.truncate(multi_pv);
-        Ok(Some(tiebroken_moves))
+        // Not atomic with respect to concurrent submissions for the same
+        // position. The last write wins.
+        let setup = pos.clone().into_setup(EnPassantMode::Legal);
+        let multi_pv = user_eval.multi_pv();
+
+        // Never lose pvs of an existing user eval. With at most as many pvs,
+        // the existing user eval is compared in full below.
+        if self
+            .get_user_eval_blocking(setup.clone(), pos.variant())?
+            .is_some_and(|existing| existing.multi_pv() > multi_pv)
+        {
+            return Ok(false);
+        }
+
+        let num_legal_moves = pos.legal_moves().len();
+        if let Some(existing) = self.get_eval_uncached_blocking(EvalRequest {
+            budget,
+            ..EvalRequest::new(pos.clone(), multi_pv)
+        })? {
+            if existing.quality(multi_pv, num_legal_moves)
+                >= EvalQuality::new(&user_eval.pvs, user_eval.depth, multi_pv, num_legal_moves)
+            {
+                return Ok(false);
+            }
+        }
+
+        self.put_user_eval_blocking(setup, pos.variant(), user_eval)?;
+        Ok(true)
     }
 
-    fn tiebreak_moves_blocking(
+    /// Inserts or replaces the user eval of a position.
+    pub fn put_user_eval_blocking(
         &self,
+        setup: Setup,
+        variant: Variant,
+        user_eval: &UserEval,
+    ) -> Result<(), Error> {
+        let user_evals = self.user_evals.as_ref().ok_or(Error::UserEvalsDisabled)?;
+
+        let mut value = Vec::new();
+        user_eval.write(&mut value)?;
+        user_evals.put(user_eval_key(setup, variant), value)?;
+        Ok(())
+    }
+}
+
+/// Extracts up to `multi_pv` principal variations from the store. Pvs end
+/// when they repeat a position, including positions from the `history` of
+/// the game. If tablebases are given, pvs also end once they reach a
+/// position that the tablebases can score. Pvs that exceed the budget are
+/// truncated.
+pub fn get_multi_pv<S: PositionStore>(
+    store: &S,
+    tablebases: Option<&Tablebases>,
+    request: &PvRequest<'_>,
+) -> Result<Option<Analysis>, Error> {
+    let timer = METRICS.root_lookup_seconds.start_timer();
+    let root = get_scored_moves(store, &request.pos.clone().into_setup(EnPassantMode::Legal));
+    timer.observe_duration();
+    let Some(root) = root? else {
+        return Ok(None); // Root position not found
+    };
+
+    analyze(store, tablebases, request, root)
+}
+
+/// Like [`get_multi_pv()`] for many positions at once. All root positions
+/// are looked up with a single `multi_get`.
+pub fn get_multi_pvs<S: PositionStore>(
+    store: &S,
+    tablebases: Option<&Tablebases>,
+    requests: &[PvRequest<'_>],
+) -> Vec<Result<Option<Analysis>, Error>> {
+    let (keys, natural_orders): (Vec<_>, Vec<_>) = requests
+        .iter()
+        .map(|request| cdb_fen(&request.pos.clone().into_setup(EnPassantMode::Legal)))
+        .unzip();
+
+    let timer = METRICS.root_lookup_seconds.start_timer();
+    let rows = store.multi_get(&keys);
+    timer.observe_duration();
+
+    rows.into_iter()
+        .zip(natural_orders)
+        .zip(requests)
+        .map(|((row, natural_order), request)| {
+            let Some(value) = row? else {
+                return Ok(None); // Root position not found
+            };
+            let root = ScoredMoves::try_read_cdb(&mut &value[..], natural_order)?.into_sorted();
+            analyze(store, tablebases, request, root)
+        })
+        .collect()
+}
+
+fn analyze<S: PositionStore>(
+    store: &S,
+    tablebases: Option<&Tablebases>,
+    request: &PvRequest<'_>,
+    root: SortedScoredMoves,
+) -> Result<Option<Analysis>, Error> {
+    let Some(root) = multi_pv_root(store, request.pos, request.multi_pv, root)? else {
+        return Ok(None);
+    };
+
+    let timer = METRICS.pv_extension_seconds.start_timer();
+    let pvs = extend_pvs(store, tablebases, request, root.moves);
+    timer.observe_duration();
+
+    Ok(Some(Analysis {
+        pvs: pvs?,
+        ply_from_root: root.ply_from_root,
+    }))
+}
+
+pub fn get_scored_moves<S: PositionStore>(
+    store: &S,
+    setup: &Setup,
+) -> Result<Option<SortedScoredMoves>, Error> {
+    let (key, natural_order) = cdb_fen(setup);
+
+    let Some(value) = store.get(key.as_bytes())? else {
+        return Ok(None);
+    };
+
+    Ok(Some(
+        ScoredMoves::try_read_cdb(&mut &value[..], natural_order)?.into_sorted(),
+    ))
+}
+
+fn multi_pv_root<S: PositionStore>(
+    store: &S,
+    pos: &Chess,
+    multi_pv: usize,
+    root: SortedScoredMoves,
+) -> Result<Option<MultiPvRoot>, Error> {
+    if root.len() < multi_pv && root.len() < pos.legal_moves().len() {
+        return Ok(None); // Cannot satisfy number of requested pvs
+    }
+
+    let ply_from_root = root.ply_from_root();
+    let mut tiebroken_moves = tiebreak_moves(store, pos, root, multi_pv)?;
+    tiebroken_moves.sort_by_key(TiebrokenMove::sort_key);
+    tiebroken_moves.truncate(multi_pv);
+    Ok(Some(MultiPvRoot {
+        moves: tiebroken_moves,
+        ply_from_root,
+    }))
+}
+
+/// Moves from the database are not trusted to be legal.
+fn to_move(pos: &Chess, uci: &UciMove) -> Result<Move, CdbDecodeError> {
+    uci.to_move(pos)
+        .map_err(|_| CdbDecodeError::IllegalMove { uci: uci.clone() })
+}
+
+/// Best moves of a position, waiting for the scored moves of the
+/// resulting child positions.
+struct PendingTiebreak {
+    best_moves: SortedScoredMoves,
+    keys: Vec<Nibbles>,
+    natural_orders: Vec<NaturalOrder>,
+}
+
+impl PendingTiebreak {
+    fn new(
         pos: &Chess,
         moves: SortedScoredMoves,
         at_least: usize,
-    ) -> Result<Vec<TiebrokenMove>, DbError> {
+    ) -> Result<PendingTiebreak, CdbDecodeError> {
         let best_moves = moves.into_best_moves(at_least);
 
-        let (keys, natural_orders): (Vec<_>, Vec<_>) = best_moves
+        let (keys, natural_orders) = best_moves
             .moves()
             .iter()
             .map(|entry| {
                 let mut child = pos.clone();
-                let m = entry.uci.to_move(&child).unwrap();
+                let m = to_move(&child, &entry.uci)?;
                 child.play_unchecked(&m);
-                cdb_fen(&child.into_setup(EnPassantMode::Legal))
+                Ok(cdb_fen(&child.into_setup(EnPassantMode::Legal)))
             })
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
             .unzip();
 
-        best_moves
+        Ok(PendingTiebreak {
+            best_moves,
+            keys,
+            natural_orders,
+        })
+    }
+
+    /// Resolves the tiebreak with one row for each key, in order.
+    fn resolve<V, I>(self, rows: I) -> Result<Vec<TiebrokenMove>, Error>
+    where
+        V: Deref<Target = [u8]>,
+        I: Iterator<Item = Result<Option<V>, Error>>,
+    {
+        self.best_moves
             .into_moves()
             .into_iter()
-            .zip(self.inner.multi_get(&keys).into_iter().zip(natural_orders))
+            .zip(rows.zip(self.natural_orders))
             .map(|(entry, (row, natural_order))| {
                 Ok(TiebrokenMove {
                     uci: entry.uci,
                     score: entry.score,
-                    scored_child_moves: match row {
-                        Ok(Some(value)) => {
-                            Some(ScoredMoves::read_cdb(&mut &value[..], natural_order))
+                    scored_child_moves: match row? {
+                        Some(value) => {
+                            Some(ScoredMoves::try_read_cdb(&mut &value[..], natural_order)?)
                         }
-                        Ok(None) => None,
-                        Err(err) => return Err(err),
+                        None => None,
                     },
                 })
             })
             .collect::<Result<_, _>>()
     }
+}
 
-    pub fn get_blocking(&self, setup: Setup) -> Result<Option<SortedScoredMoves>, DbError> {
-        let (key, natural_order) = cdb_fen(&setup);
-
-        Ok(self
-            .inner
-            .get_pinned(key.as_bytes())?
-            .map(|value| ScoredMoves::read_cdb(&mut &value[..], natural_order).into_sorted()))
-    }
+fn tiebreak_moves<S: PositionStore>(
+    store: &S,
+    pos: &Chess,
+    moves: SortedScoredMoves,
+    at_least: usize,
+) -> Result<Vec<TiebrokenMove>, Error> {
+    let pending = PendingTiebreak::new(pos, moves, at_least)?;
+    let rows = store.multi_get(&pending.keys);
+    pending.resolve(rows.into_iter())
+}
 
-    async fn extend_pv(self: Arc<Self>, pos: Chess, begin: TiebrokenMove) -> Result<Pv, DbError> {
-        task::spawn_blocking(move || self.extend_pv_blocking(pos, begin))
-            .await
-            .expect("extend pv blocking")
-    }
+struct PvState {
+    pos: Chess,
+    score: WhiteScore,
+    line: Vec<UciMove>,
+    seen_hashes: HashSet<Zobrist64>,
+    top_move: Option<TiebrokenMove>,
+    truncated: bool,
+}
 
-    fn extend_pv_blocking(&self, mut pos: Chess, begin: TiebrokenMove) -> Result<Pv, DbError> {
-        let score = WhiteScore::from_relative(begin.score, pos.turn());
-        let mut line = vec![];
+/// Extends all pvs in lockstep, so that the child positions needed at each
+/// ply are looked up with a single `multi_get`. Once the budget is
+/// exhausted, the pvs extracted so far are returned.
+fn extend_pvs<S: PositionStore>(
+    store: &S,
+    tablebases: Option<&Tablebases>,
+    request: &PvRequest<'_>,
+    begins: Vec<TiebrokenMove>,
+) -> Result<Vec<Pv>, Error> {
+    let pos = request.pos;
+    let budget = request.budget.start();
 
-        let mut seen_hashes: HashSet<Zobrist64> = HashSet::new();
-        seen_hashes.insert(pos.zobrist_hash(EnPassantMode::Legal));
+    let mut states: Vec<PvState> = begins
+        .into_iter()
+        .map(|begin| PvState {
+            pos: pos.clone(),
+            score: WhiteScore::from_relative(begin.score, pos.turn()),
+            line: Vec::new(),
+            seen_hashes: request
+                .history
+                .iter()
+                .copied()
+                .chain([pos.zobrist_hash(EnPassantMode::Legal)])
+                .collect(),
+            top_move: Some(begin),
+            truncated: false,
+        })
+        .collect();
 
-        let mut maybe_top_move = Some(begin);
+    let mut lookups = 0;
+    loop {
+        let mut keys = Vec::new();
+        let mut pending = Vec::new();
 
-        loop {
-            let Some(top_move) = maybe_top_move else {
-                break;
+        for (i, state) in states.iter_mut().enumerate() {
+            let Some(top_move) = state.top_move.take() else {
+                continue;
             };
 
-            let m = top_move.uci.to_move(&pos).expect("top move is legal");
-            line.push(UciMove::from_chess960(&m));
+            if state.line.len() >= budget.max_plies {
+                state.truncated = true;
+                continue;
+            }
+
+            let m = to_move(&state.pos, &top_move.uci)?;
+            state.line.push(UciMove::from_chess960(&m));
 
             let Some(scored_moves) = top_move.scored_child_moves else {
-                break;
+                continue;
             };
 
-            pos.play_unchecked(&m);
+            state.pos.play_unchecked(&m);
+
+            // chessdb.cn is not authoritative in tablebase territory, but
+            // still fills in for missing tables.
+            if let Some(tablebases) = tablebases {
+                if tablebases.can_score(&state.pos)? {
+                    continue;
+                }
+            }
 
-            if !seen_hashes.insert(pos.zobrist_hash(EnPassantMode::Legal)) {
-                break;
+            if !state
+                .seen_hashes
+                .insert(state.pos.zobrist_hash(EnPassantMode::Legal))
+            {
+                continue;
             }
 
-            maybe_top_move = self
-                .tiebreak_moves_blocking(&pos, scored_moves.into_sorted(), 1)?
+            let mut tiebreak = PendingTiebreak::new(&state.pos, scored_moves.into_sorted(), 1)?;
+            keys.append(&mut tiebreak.keys);
+            pending.push((i, tiebreak));
+        }
+
+        if pending.is_empty() {
+            break;
+        }
+
+        lookups += keys.len();
+        if lookups > budget.max_lookups || budget.is_expired() {
+            for (i, _) in pending {
+                states[i].truncated = true;
+            }
+            break;
+        }
+
+        let mut rows = store.multi_get(&keys).into_iter();
+        for (i, tiebreak) in pending {
+            let num_rows = tiebreak.natural_orders.len();
+            states[i].top_move = tiebreak
+                .resolve(rows.by_ref().take(num_rows))?
                 .into_iter()
                 .min_by_key(TiebrokenMove::sort_key);
         }
+    }
 
-        Ok(Pv {
-            moves: line,
-            cp: score,
+    Ok(states
+        .into_iter()
+        .map(|state| Pv {
+            score: state.score,
+            moves: state.line,
+            truncated: state.truncated,
         })
-    }
+        .collect())
 }
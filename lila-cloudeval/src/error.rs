@@ -1,30 +1,99 @@
+use std::io;
+
 use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
 };
-use shakmaty::{Chess, PositionError};
+use shakmaty::{uci::UciMove, variant::VariantPosition, PositionError};
+use shakmaty_syzygy::SyzygyError;
 use terarkdb::Error as DbError;
 use thiserror::Error;
 
+use crate::{
+    cdb_moves::CdbDecodeError,
+    metrics::Outcome,
+    user_eval::{InvalidUserEval, UserEvalDecodeError, UserEvalEncodeError},
+};
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("database error: {0}")]
     DbError(#[from] DbError),
+    #[error("corrupt database record: {0}")]
+    CdbDecodeError(#[from] CdbDecodeError),
+    #[error("corrupt user eval: {0}")]
+    UserEvalDecodeError(#[from] UserEvalDecodeError),
+    #[error("could not store user eval: {0}")]
+    UserEvalEncodeError(#[from] UserEvalEncodeError),
+    #[error("tablebase error: {0}")]
+    SyzygyError(#[from] SyzygyError),
+    #[error("could not open tablebases: {0}")]
+    OpenTablebases(io::Error),
+    #[error("user evals are not enabled")]
+    UserEvalsDisabled,
+    #[error("overloaded, try again later")]
+    Overloaded,
     #[error("bad request: {0}")]
-    PositionError(#[from] PositionError<Chess>),
+    PositionError(Box<PositionError<VariantPosition>>),
+    #[error("bad request: illegal move {uci}")]
+    IllegalMove { uci: UciMove },
     #[error("bad request: requested {n} pvs, but only 5 allowed")]
     MultiPvRange { n: usize },
+    #[error("bad request: {0}")]
+    InvalidUserEval(#[from] InvalidUserEval),
+    #[error("bad request: {0}")]
+    InvalidJson(#[from] serde_json::Error),
+}
+
+impl From<PositionError<VariantPosition>> for Error {
+    fn from(err: PositionError<VariantPosition>) -> Error {
+        Error::PositionError(Box::new(err))
+    }
+}
+
+impl Error {
+    /// Label for request metrics.
+    pub fn outcome(&self) -> &'static str {
+        match self {
+            Error::DbError(_)
+            | Error::CdbDecodeError(_)
+            | Error::UserEvalDecodeError(_)
+            | Error::UserEvalEncodeError(_) => "db_error",
+            Error::SyzygyError(_) | Error::OpenTablebases(_) => "tablebase_error",
+            Error::UserEvalsDisabled => "user_evals_disabled",
+            Error::Overloaded => "overloaded",
+            Error::PositionError(_)
+            | Error::IllegalMove { .. }
+            | Error::MultiPvRange { .. }
+            | Error::InvalidUserEval(_)
+            | Error::InvalidJson(_) => "bad_request",
+        }
+    }
 }
 
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
-        (
+        let outcome = Outcome(self.outcome());
+        let mut response = (
             match self {
-                Error::DbError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-                Error::PositionError(_) | Error::MultiPvRange { .. } => StatusCode::BAD_REQUEST,
+                Error::DbError(_)
+                | Error::CdbDecodeError(_)
+                | Error::UserEvalDecodeError(_)
+                | Error::UserEvalEncodeError(_)
+                | Error::SyzygyError(_)
+                | Error::OpenTablebases(_) => StatusCode::INTERNAL_SERVER_ERROR,
+                Error::UserEvalsDisabled => StatusCode::NOT_IMPLEMENTED,
+                Error::Overloaded => StatusCode::SERVICE_UNAVAILABLE,
+                Error::PositionError(_)
+                | Error::IllegalMove { .. }
+                | Error::MultiPvRange { .. }
+                | Error::InvalidUserEval(_)
+                | Error::InvalidJson(_) => StatusCode::BAD_REQUEST,
             },
             self.to_string(),
         )
-            .into_response()
+            .into_response();
+        response.extensions_mut().insert(outcome);
+        response
     }
 }
@@ -0,0 +1,174 @@
+use serde::Serialize;
+
+use crate::{
+    database::{Analysis, Pv},
+    user_eval::UserEval,
+};
+
+// chessdb.cn does not record search depth or node counts, but lila uses them
+// to compare the quality of evals. They are synthesized as follows:
+//
+// * The depth is the length of the shortest pv in plies. The pv ends where
+//   chessdb.cn has no further analysis, so this is a rough measure of how
+//   deeply the tree below the position has been explored. Truncated pvs
+//   count as arbitrarily long, so that the depth does not depend on the
+//   budget or on timing.
+// * Positions that are connected to the root (known ply from root) have
+//   their scores backed up through the tree, so they get a bonus.
+// * The depth is clamped to a range that is plausible for engine analysis.
+// * The node count grows quadratically with the synthesized depth.
+const MIN_DEPTH: u32 = 20;
+const MAX_DEPTH: u32 = 99;
+const PLY_FROM_ROOT_DEPTH_BONUS: u32 = 10;
+const KNODES_PER_DEPTH_SQUARED: u64 = 100;
+
+/// Length of the shortest pv. Truncated pvs would have continued, so they
+/// count as arbitrarily long.
+fn shortest_pv(pvs: &[Pv]) -> usize {
+    pvs.iter()
+        .map(|pv| {
+            if pv.truncated {
+                usize::MAX
+            } else {
+                pv.moves.len()
+            }
+        })
+        .min()
+        .unwrap_or(0)
+}
+
+fn synthesize_depth(analysis: &Analysis) -> u32 {
+    let shortest_pv = shortest_pv(&analysis.pvs);
+
+    let bonus = if analysis.ply_from_root.is_some() {
+        PLY_FROM_ROOT_DEPTH_BONUS
+    } else {
+        0
+    };
+
+    u32::try_from(shortest_pv)
+        .unwrap_or(u32::MAX)
+        .saturating_add(bonus)
+        .clamp(MIN_DEPTH, MAX_DEPTH)
+}
+
+fn synthesize_knodes(depth: u32) -> u64 {
+    u64::from(depth) * u64::from(depth) * KNODES_PER_DEPTH_SQUARED
+}
+
+#[derive(Serialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EvalSource {
+    Chessdb,
+    Tablebase,
+    User,
+}
+
+/// Quality of an eval for a particular request. Compares by whether the
+/// requested number of pvs is satisfied, then depth, then length of the
+/// shortest untruncated pv.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub struct EvalQuality {
+    pub satisfies_multi_pv: bool,
+    pub depth: u32,
+    pub pv_len: usize,
+}
+
+impl EvalQuality {
+    pub fn new(pvs: &[Pv], depth: u32, multi_pv: usize, num_legal_moves: usize) -> EvalQuality {
+        EvalQuality {
+            satisfies_multi_pv: !pvs.is_empty() && pvs.len() >= multi_pv.min(num_legal_moves),
+            depth,
+            pv_len: shortest_pv(pvs),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Eval {
+    Chessdb(Analysis),
+    /// Tablebase-perfect analysis, reported with the maximum depth.
+    Tablebase(Analysis),
+    User(UserEval),
+}
+
+impl Eval {
+    /// Picks the better of the chessdb.cn analysis and the user eval of a
+    /// position. User evals are only preferred if they are strictly better,
+    /// and are truncated to the requested number of pvs.
+    pub fn choose(
+        chessdb: Option<Analysis>,
+        user: Option<UserEval>,
+        multi_pv: usize,
+        num_legal_moves: usize,
+    ) -> Option<Eval> {
+        let candidates = [
+            chessdb.map(Eval::Chessdb),
+            user.map(|mut user| {
+                user.pvs.truncate(multi_pv);
+                Eval::User(user)
+            }),
+        ];
+
+        let mut best: Option<(Eval, EvalQuality)> = None;
+        for eval in candidates.into_iter().flatten() {
+            let quality = eval.quality(multi_pv, num_legal_moves);
+            if quality.satisfies_multi_pv && best.as_ref().is_none_or(|(_, best)| quality > *best) {
+                best = Some((eval, quality));
+            }
+        }
+        best.map(|(eval, _)| eval)
+    }
+
+    pub fn source(&self) -> EvalSource {
+        match self {
+            Eval::Chessdb(_) => EvalSource::Chessdb,
+            Eval::Tablebase(_) => EvalSource::Tablebase,
+            Eval::User(_) => EvalSource::User,
+        }
+    }
+
+    pub fn pvs(&self) -> &[Pv] {
+        match self {
+            Eval::Chessdb(analysis) | Eval::Tablebase(analysis) => &analysis.pvs,
+            Eval::User(user) => &user.pvs,
+        }
+    }
+
+    /// Cuts all pvs to at most `max_plies`.
+    pub fn truncate_pvs(&mut self, max_plies: usize) {
+        let pvs = match self {
+            Eval::Chessdb(analysis) | Eval::Tablebase(analysis) => &mut analysis.pvs,
+            Eval::User(user) => &mut user.pvs,
+        };
+        for pv in pvs {
+            pv.truncate(max_plies);
+        }
+    }
+
+    pub fn into_pvs(self) -> Vec<Pv> {
+        match self {
+            Eval::Chessdb(analysis) | Eval::Tablebase(analysis) => analysis.pvs,
+            Eval::User(user) => user.pvs,
+        }
+    }
+
+    pub fn depth(&self) -> u32 {
+        match self {
+            Eval::Chessdb(analysis) => synthesize_depth(analysis),
+            Eval::Tablebase(_) => MAX_DEPTH,
+            Eval::User(user) => user.depth,
+        }
+    }
+
+    pub fn knodes(&self) -> u64 {
+        match self {
+            Eval::Chessdb(_) | Eval::Tablebase(_) => synthesize_knodes(self.depth()),
+            Eval::User(user) => user.knodes,
+        }
+    }
+
+    pub fn quality(&self, multi_pv: usize, num_legal_moves: usize) -> EvalQuality {
+        EvalQuality::new(self.pvs(), self.depth(), multi_pv, num_legal_moves)
+    }
+}
@@ -1,7 +1,16 @@
 #![forbid(unsafe_code)]
 
 pub mod binary_fen;
+pub mod cache;
 pub mod cdb_fen;
 pub mod cdb_moves;
 pub mod database;
 pub mod error;
+pub mod eval;
+pub mod lookup_pool;
+pub mod metrics;
+pub mod protocol;
+pub mod score;
+pub mod store;
+pub mod tablebase;
+pub mod user_eval;
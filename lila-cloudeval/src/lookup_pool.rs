@@ -0,0 +1,76 @@
+use std::{
+    num::NonZeroUsize,
+    panic::{self, AssertUnwindSafe},
+    thread,
+};
+
+use crossbeam_channel::{Sender, TrySendError};
+use tokio::sync::oneshot;
+
+use crate::error::Error;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// The lookup queue is full.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Overloaded;
+
+impl From<Overloaded> for Error {
+    fn from(_: Overloaded) -> Error {
+        Error::Overloaded
+    }
+}
+
+/// Fixed number of threads for blocking database lookups.
+///
+/// Unlike `tokio::task::spawn_blocking`, the pool does not grow under load.
+/// Jobs beyond the bounded queue are rejected immediately, so that latency
+/// degrades gracefully instead of thrashing the disk.
+#[derive(Debug)]
+pub struct LookupPool {
+    sender: Sender<Job>,
+}
+
+impl LookupPool {
+    /// Starts `threads` lookup threads, which is also the maximum number of
+    /// lookups in flight. Up to `queue` further jobs wait for a free thread.
+    pub fn new(threads: NonZeroUsize, queue: usize) -> LookupPool {
+        let (sender, receiver) = crossbeam_channel::bounded::<Job>(queue);
+        for i in 0..threads.get() {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("lookup-{i}"))
+                .spawn(move || {
+                    // Exits once the pool is dropped.
+                    while let Ok(job) = receiver.recv() {
+                        job();
+                    }
+                })
+                .expect("spawn lookup thread");
+        }
+        LookupPool { sender }
+    }
+
+    /// Runs a blocking job on the pool and waits for its result. Panics in
+    /// the job are propagated.
+    pub async fn run<F, T>(&self, f: F) -> Result<T, Overloaded>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .try_send(Box::new(move || {
+                let _ = tx.send(panic::catch_unwind(AssertUnwindSafe(f)));
+            }))
+            .map_err(|err| match err {
+                TrySendError::Full(_) => Overloaded,
+                TrySendError::Disconnected(_) => unreachable!("lookup threads never exit early"),
+            })?;
+
+        match rx.await.expect("lookup job") {
+            Ok(res) => Ok(res),
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+}
@@ -1,31 +1,108 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
 
 use axum::{
-    extract::{FromRef, Query, State},
-    routing::{get, Router},
+    body::Body,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        FromRef, Query, State,
+    },
+    http::{header, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post, Router},
     Json,
 };
 use clap::Parser as _;
+use futures_util::stream::{self, StreamExt as _};
 use lila_cloudeval::{
-    database::{Database, DatabaseOpt, Pv},
+    database::{Database, DatabaseOpt, EvalRequest, PvBudget},
     error::Error,
+    metrics::{observe_failures, METRICS},
+    protocol::{
+        parse_batch, play_moves, BatchItem, BatchResult, ClientMessage, EvalGet, EvalHit, EvalPut,
+        LilaVariant, MultiPv, QueryAll, ServerMessage,
+    },
+};
+use serde::Deserialize;
+use serde_with::{
+    formats::CommaSeparator, serde_as, DisplayFromStr, StringWithSeparator, TryFromInto,
 };
-use serde::{Deserialize, Serialize};
-use serde_with::{serde_as, DisplayFromStr, TryFromInto};
-use shakmaty::{fen::Fen, CastlingMode};
-use tokio::net::TcpListener;
+use shakmaty::{fen::Fen, uci::UciMove, EnPassantMode};
+use tokio::{net::TcpListener, task::JoinSet};
+
+const MAX_IN_FLIGHT_PER_SOCKET: usize = 64;
+
+const BATCH_CHUNK_SIZE: usize = 16;
+const MAX_CONCURRENT_BATCH_CHUNKS: usize = 4;
 
 #[derive(Debug, clap::Parser)]
 struct Opt {
     #[clap(flatten)]
     db: DatabaseOpt,
+    #[clap(flatten)]
+    budget: BudgetOpt,
     #[arg(long)]
     bind: SocketAddr,
 }
 
+/// Server defaults for the work spent extracting pvs. Requests can ask for
+/// less, but not for more.
+#[derive(Debug, Copy, Clone, clap::Args)]
+struct BudgetOpt {
+    /// Maximum number of plies in each pv.
+    #[arg(long, default_value = "100")]
+    max_pv_plies: usize,
+    /// Maximum number of positions looked up to extend the pvs of a
+    /// request.
+    #[arg(long, default_value = "2000")]
+    max_pv_lookups: usize,
+    /// Time after which pvs are no longer extended, in milliseconds from
+    /// the start of the request. For batch items, measured from when
+    /// extending the pvs of the item begins.
+    #[arg(long, default_value = "1000")]
+    pv_deadline_ms: u64,
+}
+
+impl BudgetOpt {
+    fn budget(
+        &self,
+        max_plies: Option<usize>,
+        max_lookups: Option<usize>,
+        deadline_ms: Option<u64>,
+    ) -> PvBudget {
+        let deadline_ms = deadline_ms.map_or(self.pv_deadline_ms, |ms| ms.min(self.pv_deadline_ms));
+        PvBudget {
+            max_plies: max_plies.map_or(self.max_pv_plies, |n| n.min(self.max_pv_plies)),
+            max_lookups: max_lookups.map_or(self.max_pv_lookups, |n| n.min(self.max_pv_lookups)),
+            deadline: Some(Instant::now() + Duration::from_millis(deadline_ms)),
+            time_limit: None,
+        }
+    }
+
+    fn default_budget(&self) -> PvBudget {
+        self.budget(None, None, None)
+    }
+
+    /// Budget for each item of a batch. The items of a chunk are resolved
+    /// one after another, so each starts its own time limit.
+    fn batch_budget(&self) -> PvBudget {
+        PvBudget {
+            deadline: None,
+            time_limit: Some(Duration::from_millis(self.pv_deadline_ms)),
+            ..self.default_budget()
+        }
+    }
+}
+
 #[derive(FromRef, Clone)]
 struct AppState {
     db: Arc<Database>,
+    budget: BudgetOpt,
 }
 
 #[tokio::main]
@@ -33,70 +110,299 @@ async fn main() {
     let opt = Opt::parse();
 
     let app = Router::new()
-        .route("/", get(query_pv))
+        .route("/", get(query_pv).post(submit_eval))
+        .route("/query-all", get(query_all))
+        .route("/batch", post(batch))
+        .route_layer(middleware::from_fn(observe_failures))
+        .route("/socket", get(socket))
+        .route("/metrics", get(metrics))
         .with_state(AppState {
             db: Arc::new(Database::open_read_only_blocking(&opt.db).expect("open database")),
+            budget: opt.budget,
         });
 
     let listener = TcpListener::bind(&opt.bind).await.expect("bind");
     axum::serve(listener, app).await.expect("serve");
 }
 
-#[derive(Copy, Clone, Debug)]
-struct MultiPv(usize);
+#[serde_as]
+#[derive(Deserialize)]
+struct PvQuery {
+    #[serde_as(as = "DisplayFromStr")]
+    fen: Fen,
+    #[serde_as(as = "TryFromInto<usize>")]
+    #[serde(default)]
+    multi_pv: MultiPv,
+    #[serde(default)]
+    variant: LilaVariant,
+    /// Moves played from `fen`, for example `e2e4,e7e5`. The final position
+    /// is evaluated.
+    #[serde_as(as = "StringWithSeparator<CommaSeparator, UciMove>")]
+    #[serde(default)]
+    moves: Vec<UciMove>,
+    /// Maximum number of plies in each pv.
+    max_plies: Option<usize>,
+    /// Maximum number of positions looked up to extend the pvs.
+    max_lookups: Option<usize>,
+    /// Time after which pvs are no longer extended, in milliseconds.
+    deadline_ms: Option<u64>,
+    path: Option<String>,
+}
 
-impl Default for MultiPv {
-    fn default() -> MultiPv {
-        MultiPv(1)
-    }
+async fn eval(
+    db: Arc<Database>,
+    fen: Fen,
+    variant: LilaVariant,
+    moves: Vec<UciMove>,
+    multi_pv: MultiPv,
+    budget: PvBudget,
+    path: Option<String>,
+) -> Result<Option<EvalHit>, Error> {
+    METRICS.observe_multi_pv(multi_pv.into());
+
+    let (fen, request) = if moves.is_empty() {
+        let pos = variant.position(fen.clone())?;
+        (
+            fen,
+            EvalRequest {
+                budget,
+                ..EvalRequest::new(pos, multi_pv.into())
+            },
+        )
+    } else {
+        let (pos, history) = play_moves(variant.position(fen)?, &moves)?;
+        (
+            Fen::from_position(pos.clone(), EnPassantMode::Legal),
+            EvalRequest {
+                pos,
+                multi_pv: multi_pv.into(),
+                history,
+                budget,
+            },
+        )
+    };
+
+    let eval = db.get_eval(request).await?;
+
+    Ok(eval.map(|eval| EvalHit::new(fen, path, eval)))
 }
 
-impl From<MultiPv> for usize {
-    fn from(MultiPv(n): MultiPv) -> usize {
-        n
-    }
+#[axum::debug_handler(state = AppState)]
+async fn query_pv(
+    State(db): State<Arc<Database>>,
+    State(budget): State<BudgetOpt>,
+    Query(pv_query): Query<PvQuery>,
+) -> Result<Json<Option<EvalHit>>, Error> {
+    let budget = budget.budget(
+        pv_query.max_plies,
+        pv_query.max_lookups,
+        pv_query.deadline_ms,
+    );
+    let eval_hit = eval(
+        db,
+        pv_query.fen,
+        pv_query.variant,
+        pv_query.moves,
+        pv_query.multi_pv,
+        budget,
+        pv_query.path,
+    )
+    .await?;
+    METRICS.observe_eval_hit(eval_hit.as_ref());
+    Ok(Json(eval_hit))
 }
 
-impl TryFrom<usize> for MultiPv {
-    type Error = Error;
+/// Resolves a chunk of batch items, sharing database lookups.
+async fn batch_chunk(
+    db: Arc<Database>,
+    budget: BudgetOpt,
+    items: Vec<Result<BatchItem, Error>>,
+) -> Vec<BatchResult> {
+    let budget = budget.batch_budget();
+    let mut requests = Vec::new();
+    let fens: Vec<Result<Fen, Error>> = items
+        .into_iter()
+        .map(|item| {
+            let item = item?;
+            METRICS.observe_multi_pv(item.multi_pv.into());
+            requests.push(EvalRequest {
+                budget,
+                ..EvalRequest::new(
+                    item.variant.position(item.fen.clone())?,
+                    item.multi_pv.into(),
+                )
+            });
+            Ok(item.fen)
+        })
+        .collect();
 
-    fn try_from(n: usize) -> Result<MultiPv, Error> {
-        if n > 5 {
-            Err(Error::MultiPvRange { n })
-        } else {
-            Ok(MultiPv(n))
-        }
+    let mut evals = db.get_evals(requests).await.into_iter();
+    fens.into_iter()
+        .map(|fen| {
+            let res = fen.and_then(|fen| {
+                let eval = evals.next().expect("eval for each request")?;
+                Ok(eval.map(|eval| EvalHit::new(fen, None, eval)))
+            });
+            METRICS.observe_eval(&res);
+            BatchResult::from(res)
+        })
+        .collect()
+}
+
+/// Accepts a JSON array or newline delimited JSON of batch items, and
+/// streams back newline delimited results in the same order.
+#[axum::debug_handler(state = AppState)]
+async fn batch(
+    State(db): State<Arc<Database>>,
+    State(budget): State<BudgetOpt>,
+    body: String,
+) -> Result<Response, Error> {
+    let mut items = parse_batch(&body)?.into_iter().peekable();
+    let mut chunks = Vec::new();
+    while items.peek().is_some() {
+        chunks.push(items.by_ref().take(BATCH_CHUNK_SIZE).collect::<Vec<_>>());
     }
+
+    let lines = stream::iter(chunks)
+        .map(move |chunk| batch_chunk(db.clone(), budget, chunk))
+        .buffered(MAX_CONCURRENT_BATCH_CHUNKS)
+        .flat_map(stream::iter)
+        .map(|res| {
+            let mut line = serde_json::to_vec(&res).expect("serialize batch result");
+            line.push(b'\n');
+            Ok::<_, Infallible>(line)
+        });
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines),
+    )
+        .into_response())
 }
 
 #[serde_as]
 #[derive(Deserialize)]
-struct PvQuery {
+struct QueryAllQuery {
     #[serde_as(as = "DisplayFromStr")]
     fen: Fen,
-    #[serde_as(as = "TryFromInto<usize>")]
     #[serde(default)]
-    multi_pv: MultiPv,
+    variant: LilaVariant,
+}
+
+#[axum::debug_handler(state = AppState)]
+async fn query_all(
+    State(db): State<Arc<Database>>,
+    Query(query): Query<QueryAllQuery>,
+) -> Result<Json<Option<QueryAll>>, Error> {
+    let pos = query.variant.position(query.fen.clone())?;
+    let moves = db.query_all(pos.clone()).await?;
+    METRICS.observe_hit(moves.is_some());
+    Ok(Json(
+        moves.map(|moves| QueryAll::new(query.fen, &pos, moves)),
+    ))
 }
 
-#[derive(Serialize)]
-struct PvResponse {
-    pvs: Option<Vec<Pv>>,
+async fn put_eval(db: Arc<Database>, eval_put: EvalPut, budget: PvBudget) -> Result<bool, Error> {
+    let (pos, user_eval) = eval_put.into_user_eval(SystemTime::now())?;
+    db.submit_user_eval(pos, user_eval, budget).await
 }
 
 #[axum::debug_handler(state = AppState)]
-async fn query_pv(
+async fn submit_eval(
     State(db): State<Arc<Database>>,
-    Query(pv_query): Query<PvQuery>,
-) -> Result<Json<PvResponse>, Error> {
-    Ok(Json(PvResponse {
-        pvs: db
-            .get_multi_pv(
-                pv_query.fen.into_position(CastlingMode::Chess960)?,
-                pv_query.multi_pv.into(),
-            )
-            .await?,
-    }))
+    State(budget): State<BudgetOpt>,
+    Json(eval_put): Json<EvalPut>,
+) -> Result<StatusCode, Error> {
+    let stored = put_eval(db, eval_put, budget.default_budget()).await?;
+    METRICS.observe_submit(stored);
+    Ok(if stored {
+        StatusCode::CREATED
+    } else {
+        StatusCode::NO_CONTENT
+    })
+}
+
+#[axum::debug_handler(state = AppState)]
+async fn metrics(State(db): State<Arc<Database>>) -> Response {
+    (
+        [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        db.render_metrics(),
+    )
+        .into_response()
+}
+
+#[axum::debug_handler(state = AppState)]
+async fn socket(
+    State(db): State<Arc<Database>>,
+    State(budget): State<BudgetOpt>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.on_upgrade(move |socket| handle_socket(db, budget, socket))
+}
+
+async fn handle_socket(db: Arc<Database>, budget: BudgetOpt, mut socket: WebSocket) {
+    // Requests are answered as soon as they are ready, not necessarily in
+    // order. lila-ws matches responses by fen and path.
+    let mut in_flight = JoinSet::new();
+
+    loop {
+        tokio::select! {
+            msg = socket.recv(), if in_flight.len() < MAX_IN_FLIGHT_PER_SOCKET => {
+                let Some(Ok(msg)) = msg else {
+                    break; // Closed
+                };
+                let Message::Text(text) = msg else {
+                    continue;
+                };
+                match serde_json::from_str(&text) {
+                    Ok(ClientMessage::EvalGet(EvalGet { fen, path, mpv, variant })) => {
+                        let db = db.clone();
+                        let budget = budget.default_budget();
+                        in_flight.spawn(async move {
+                            let res =
+                                eval(db, fen, variant, Vec::new(), mpv, budget, path).await;
+                            METRICS.observe_eval(&res);
+                            res
+                        });
+                    }
+                    Ok(ClientMessage::EvalPut(eval_put)) => {
+                        // Submissions are not answered.
+                        let db = db.clone();
+                        let budget = budget.default_budget();
+                        in_flight.spawn(async move {
+                            match put_eval(db, eval_put, budget).await {
+                                Ok(stored) => METRICS.observe_submit(stored),
+                                Err(err) => {
+                                    METRICS.observe_error(&err);
+                                    eprintln!("evalPut: {err}");
+                                }
+                            }
+                            Ok(None)
+                        });
+                    }
+                    Err(_) => continue, // Ignore unknown messages
+                }
+            }
+            Some(res) = in_flight.join_next(), if !in_flight.is_empty() => {
+                let res = match res {
+                    Ok(res) => res,
+                    Err(err) => {
+                        eprintln!("socket task failed: {err}");
+                        continue;
+                    }
+                };
+                // Misses and errors are not answered.
+                let Ok(Some(eval_hit)) = res else {
+                    continue;
+                };
+                let text = serde_json::to_string(&ServerMessage::EvalHit(eval_hit))
+                    .expect("serialize eval hit");
+                if socket.send(Message::Text(text)).await.is_err() {
+                    break; // Closed
+                }
+            }
+        }
+    }
 }
 
 // In: {"t":"evalGet","d":{"fen":"r1bqkbnr/pppp1ppp/2n5/1B2p3/4P3/5N2/PPPP1PPP/RNBQK2R b KQkq - 3 3","path":"/?WG)8\\M(D","mpv":2}}
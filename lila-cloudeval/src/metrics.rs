@@ -0,0 +1,213 @@
+use std::sync::LazyLock;
+
+use axum::{extract::Request, middleware::Next, response::Response};
+use prometheus::{
+    exponential_buckets, linear_buckets, Encoder as _, Histogram, HistogramOpts, IntCounterVec,
+    IntGauge, Opts, Registry, TextEncoder,
+};
+use terarkdb::Db;
+
+use crate::{cache::AnalysisCache, error::Error, protocol::EvalHit};
+
+/// Server metrics, exported in the Prometheus text format.
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    pub root_lookup_seconds: Histogram,
+    pub pv_extension_seconds: Histogram,
+    pv_plies: Histogram,
+    multi_pv: Histogram,
+    block_cache_usage_bytes: IntGauge,
+    estimate_num_keys: IntGauge,
+    estimate_table_readers_mem_bytes: IntGauge,
+    analysis_cache_entries: IntGauge,
+    analysis_cache_hits: IntGauge,
+    analysis_cache_misses: IntGauge,
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+    fn new() -> Metrics {
+        let registry =
+            Registry::new_custom(Some("cloudeval".to_owned()), None).expect("metrics registry");
+
+        let latency_buckets = exponential_buckets(0.0001, 2.0, 16).expect("latency buckets");
+        let metrics = Metrics {
+            requests: IntCounterVec::new(
+                Opts::new("requests_total", "Requests by outcome"),
+                &["outcome"],
+            )
+            .expect("requests_total"),
+            root_lookup_seconds: Histogram::with_opts(
+                HistogramOpts::new("root_lookup_seconds", "Time spent fetching root positions")
+                    .buckets(latency_buckets.clone()),
+            )
+            .expect("root_lookup_seconds"),
+            pv_extension_seconds: Histogram::with_opts(
+                HistogramOpts::new(
+                    "pv_extension_seconds",
+                    "Time spent extending the pvs of a position",
+                )
+                .buckets(latency_buckets),
+            )
+            .expect("pv_extension_seconds"),
+            pv_plies: Histogram::with_opts(
+                HistogramOpts::new("pv_plies", "Length of returned pvs")
+                    .buckets(exponential_buckets(1.0, 2.0, 8).expect("pv buckets")),
+            )
+            .expect("pv_plies"),
+            multi_pv: Histogram::with_opts(
+                HistogramOpts::new("multi_pv", "Number of requested pvs")
+                    .buckets(linear_buckets(1.0, 1.0, 5).expect("multi pv buckets")),
+            )
+            .expect("multi_pv"),
+            block_cache_usage_bytes: IntGauge::new(
+                "db_block_cache_usage_bytes",
+                "Memory used by the block cache of the chessdb.cn dump",
+            )
+            .expect("db_block_cache_usage_bytes"),
+            estimate_num_keys: IntGauge::new(
+                "db_estimate_num_keys",
+                "Estimated number of positions in the chessdb.cn dump",
+            )
+            .expect("db_estimate_num_keys"),
+            estimate_table_readers_mem_bytes: IntGauge::new(
+                "db_estimate_table_readers_mem_bytes",
+                "Memory used by table readers of the chessdb.cn dump, excluding the block cache",
+            )
+            .expect("db_estimate_table_readers_mem_bytes"),
+            analysis_cache_entries: IntGauge::new(
+                "analysis_cache_entries",
+                "Positions in the analysis cache",
+            )
+            .expect("analysis_cache_entries"),
+            analysis_cache_hits: IntGauge::new(
+                "analysis_cache_hits",
+                "Lookups answered from the analysis cache since startup",
+            )
+            .expect("analysis_cache_hits"),
+            analysis_cache_misses: IntGauge::new(
+                "analysis_cache_misses",
+                "Lookups not answered from the analysis cache since startup",
+            )
+            .expect("analysis_cache_misses"),
+            registry,
+        };
+
+        for collector in [
+            Box::new(metrics.requests.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(metrics.root_lookup_seconds.clone()),
+            Box::new(metrics.pv_extension_seconds.clone()),
+            Box::new(metrics.pv_plies.clone()),
+            Box::new(metrics.multi_pv.clone()),
+            Box::new(metrics.block_cache_usage_bytes.clone()),
+            Box::new(metrics.estimate_num_keys.clone()),
+            Box::new(metrics.estimate_table_readers_mem_bytes.clone()),
+            Box::new(metrics.analysis_cache_entries.clone()),
+            Box::new(metrics.analysis_cache_hits.clone()),
+            Box::new(metrics.analysis_cache_misses.clone()),
+        ] {
+            metrics
+                .registry
+                .register(collector)
+                .expect("register metric");
+        }
+
+        metrics
+    }
+
+    pub fn observe_multi_pv(&self, multi_pv: usize) {
+        self.multi_pv.observe(multi_pv as f64);
+    }
+
+    fn observe_outcome(&self, outcome: &str) {
+        self.requests.with_label_values(&[outcome]).inc();
+    }
+
+    /// Records the outcome of an eval request.
+    pub fn observe_eval(&self, res: &Result<Option<EvalHit>, Error>) {
+        match res {
+            Ok(eval_hit) => self.observe_eval_hit(eval_hit.as_ref()),
+            Err(err) => self.observe_error(err),
+        }
+    }
+
+    /// Records a successful eval request.
+    pub fn observe_eval_hit(&self, eval_hit: Option<&EvalHit>) {
+        if let Some(eval_hit) = eval_hit {
+            for pv in &eval_hit.pvs {
+                self.pv_plies.observe(pv.moves.len() as f64);
+            }
+        }
+        self.observe_hit(eval_hit.is_some());
+    }
+
+    /// Records a successful lookup.
+    pub fn observe_hit(&self, hit: bool) {
+        self.observe_outcome(if hit { "hit" } else { "miss" });
+    }
+
+    /// Records a successful submission.
+    pub fn observe_submit(&self, stored: bool) {
+        self.observe_outcome(if stored { "stored" } else { "not_stored" });
+    }
+
+    pub fn observe_error(&self, err: &Error) {
+        self.observe_outcome(err.outcome());
+    }
+
+    /// Reads database internals at the time of the scrape.
+    pub fn observe_db(&self, db: &Db) {
+        for (gauge, value) in [
+            (&self.block_cache_usage_bytes, db.block_cache_usage()),
+            (&self.estimate_num_keys, db.estimate_num_keys()),
+            (
+                &self.estimate_table_readers_mem_bytes,
+                db.estimate_table_readers_mem(),
+            ),
+        ] {
+            if let Some(value) = value {
+                gauge.set(i64::try_from(value).unwrap_or(i64::MAX));
+            }
+        }
+    }
+
+    /// Reads the analysis cache counters at the time of the scrape.
+    pub fn observe_cache(&self, cache: &AnalysisCache) {
+        for (gauge, value) in [
+            (&self.analysis_cache_entries, cache.len() as u64),
+            (&self.analysis_cache_hits, cache.hits()),
+            (&self.analysis_cache_misses, cache.misses()),
+        ] {
+            gauge.set(i64::try_from(value).unwrap_or(i64::MAX));
+        }
+    }
+
+    /// Renders all metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut buf = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buf)
+            .expect("encode metrics");
+        String::from_utf8(buf).expect("utf-8 metrics")
+    }
+}
+
+/// Outcome of a failed request, attached to the response for
+/// [`observe_failures()`].
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Outcome(pub &'static str);
+
+/// Middleware that records failed requests, including those rejected by
+/// extractors before reaching the handler. Handlers record their own
+/// successes.
+pub async fn observe_failures(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    if let Some(Outcome(outcome)) = response.extensions().get() {
+        METRICS.observe_outcome(outcome);
+    } else if response.status().is_client_error() {
+        METRICS.observe_outcome("bad_request");
+    }
+    response
+}
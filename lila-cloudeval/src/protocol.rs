@@ -0,0 +1,313 @@
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr, TryFromInto};
+use shakmaty::{
+    fen::Fen,
+    san::SanPlus,
+    uci::UciMove,
+    variant::{Variant, VariantPosition},
+    zobrist::{Zobrist64, ZobristHash},
+    CastlingMode, EnPassantMode, Position,
+};
+
+use crate::{
+    cdb_moves::{RelativeScore, SortedScoredMoves},
+    database::{Pv, WhiteScore},
+    error::Error,
+    eval::{Eval, EvalSource},
+    user_eval::UserEval,
+};
+
+#[derive(Copy, Clone, Debug)]
+pub struct MultiPv(usize);
+
+impl Default for MultiPv {
+    fn default() -> MultiPv {
+        MultiPv(1)
+    }
+}
+
+impl From<MultiPv> for usize {
+    fn from(MultiPv(n): MultiPv) -> usize {
+        n
+    }
+}
+
+impl TryFrom<usize> for MultiPv {
+    type Error = Error;
+
+    fn try_from(n: usize) -> Result<MultiPv, Error> {
+        if n > 5 {
+            Err(Error::MultiPvRange { n })
+        } else {
+            Ok(MultiPv(n))
+        }
+    }
+}
+
+/// Variant names as used by lila.
+#[derive(Deserialize, Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum LilaVariant {
+    #[default]
+    Standard,
+    Chess960,
+    FromPosition,
+    Antichess,
+    Atomic,
+    Crazyhouse,
+    Horde,
+    KingOfTheHill,
+    RacingKings,
+    ThreeCheck,
+}
+
+impl From<LilaVariant> for Variant {
+    fn from(variant: LilaVariant) -> Variant {
+        match variant {
+            LilaVariant::Standard | LilaVariant::Chess960 | LilaVariant::FromPosition => {
+                Variant::Chess
+            }
+            LilaVariant::Antichess => Variant::Antichess,
+            LilaVariant::Atomic => Variant::Atomic,
+            LilaVariant::Crazyhouse => Variant::Crazyhouse,
+            LilaVariant::Horde => Variant::Horde,
+            LilaVariant::KingOfTheHill => Variant::KingOfTheHill,
+            LilaVariant::RacingKings => Variant::RacingKings,
+            LilaVariant::ThreeCheck => Variant::ThreeCheck,
+        }
+    }
+}
+
+impl LilaVariant {
+    pub fn position(self, fen: Fen) -> Result<VariantPosition, Error> {
+        Ok(VariantPosition::from_setup(
+            self.into(),
+            fen.into_setup(),
+            CastlingMode::Chess960,
+        )?)
+    }
+}
+
+/// Plays moves from a start position. Returns the final position, and the
+/// hashes of earlier positions that it could still repeat.
+pub fn play_moves(
+    mut pos: VariantPosition,
+    moves: &[UciMove],
+) -> Result<(VariantPosition, Vec<Zobrist64>), Error> {
+    let mut history = Vec::new();
+    for uci in moves {
+        let m = uci
+            .to_move(&pos)
+            .map_err(|_| Error::IllegalMove { uci: uci.clone() })?;
+        if m.is_zeroing() {
+            history.clear();
+        } else {
+            history.push(pos.zobrist_hash(EnPassantMode::Legal));
+        }
+        pos.play_unchecked(&m);
+    }
+    Ok((pos, history))
+}
+
+/// Payload of an `evalHit` message for lila-ws.
+#[serde_as]
+#[derive(Serialize)]
+pub struct EvalHit {
+    #[serde_as(as = "DisplayFromStr")]
+    pub fen: Fen,
+    pub knodes: u64,
+    pub depth: u32,
+    pub pvs: Vec<Pv>,
+    pub source: EvalSource,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+impl EvalHit {
+    pub fn new(fen: Fen, path: Option<String>, eval: Eval) -> EvalHit {
+        EvalHit {
+            fen,
+            knodes: eval.knodes(),
+            depth: eval.depth(),
+            source: eval.source(),
+            pvs: eval.into_pvs(),
+            path,
+        }
+    }
+}
+
+/// All scored moves of a position, like `queryall` of chessdb.cn.
+#[serde_as]
+#[derive(Serialize)]
+pub struct QueryAll {
+    #[serde_as(as = "DisplayFromStr")]
+    pub fen: Fen,
+    pub ply_from_root: Option<u32>,
+    pub moves: Vec<QueryAllMove>,
+}
+
+#[serde_as]
+#[derive(Serialize)]
+pub struct QueryAllMove {
+    #[serde_as(as = "DisplayFromStr")]
+    pub uci: UciMove,
+    #[serde_as(as = "DisplayFromStr")]
+    pub san: SanPlus,
+    #[serde(flatten)]
+    pub score: WhiteScore,
+    /// 1-based rank of the move. Moves with equal scores share a rank.
+    pub rank: usize,
+    /// Whether the move is within the good move threshold of the best move.
+    pub good: bool,
+}
+
+impl QueryAll {
+    pub fn new<P: Position + Clone>(fen: Fen, pos: &P, moves: SortedScoredMoves) -> QueryAll {
+        let threshold = moves.good_move_threshold();
+        let ply_from_root = moves.ply_from_root();
+
+        let mut entries = Vec::with_capacity(moves.len());
+        let mut prev: Option<(RelativeScore, usize)> = None;
+        for (i, entry) in moves.into_moves().into_iter().enumerate() {
+            // chessdb.cn records are not expected to contain illegal moves.
+            let Ok(m) = entry.uci.to_move(pos) else {
+                continue;
+            };
+            let rank = match prev {
+                Some((score, rank)) if score == entry.score => rank,
+                _ => i + 1,
+            };
+            prev = Some((entry.score, rank));
+            entries.push(QueryAllMove {
+                uci: UciMove::from_chess960(&m),
+                san: SanPlus::from_move(pos.clone(), &m),
+                score: WhiteScore::from_relative(entry.score, pos.turn()),
+                rank,
+                good: entry.score >= threshold,
+            });
+        }
+
+        QueryAll {
+            fen,
+            ply_from_root,
+            moves: entries,
+        }
+    }
+}
+
+/// Item of a batch request.
+#[serde_as]
+#[derive(Deserialize)]
+pub struct BatchItem {
+    #[serde_as(as = "DisplayFromStr")]
+    pub fen: Fen,
+    #[serde_as(as = "TryFromInto<usize>")]
+    #[serde(default)]
+    pub multi_pv: MultiPv,
+    #[serde(default)]
+    pub variant: LilaVariant,
+}
+
+/// Parses the body of a batch request, either as a JSON array or as
+/// newline delimited JSON. Invalid items are reported individually.
+pub fn parse_batch(body: &str) -> Result<Vec<Result<BatchItem, Error>>, Error> {
+    let values = if body.trim_start().starts_with('[') {
+        serde_json::from_str::<Vec<serde_json::Value>>(body)?
+            .into_iter()
+            .map(Ok)
+            .collect::<Vec<_>>()
+    } else {
+        body.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str::<serde_json::Value>)
+            .collect()
+    };
+
+    Ok(values
+        .into_iter()
+        .map(|value| Ok(serde_json::from_value(value?)?))
+        .collect())
+}
+
+/// Result of an item of a batch request.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum BatchResult {
+    Hit(EvalHit),
+    Miss,
+    Error { error: String },
+}
+
+impl From<Result<Option<EvalHit>, Error>> for BatchResult {
+    fn from(res: Result<Option<EvalHit>, Error>) -> BatchResult {
+        match res {
+            Ok(Some(eval_hit)) => BatchResult::Hit(eval_hit),
+            Ok(None) => BatchResult::Miss,
+            Err(err) => BatchResult::Error {
+                error: err.to_string(),
+            },
+        }
+    }
+}
+
+/// Payload of an `evalGet` message from lila-ws.
+#[serde_as]
+#[derive(Deserialize)]
+pub struct EvalGet {
+    #[serde_as(as = "DisplayFromStr")]
+    pub fen: Fen,
+    pub path: Option<String>,
+    #[serde_as(as = "TryFromInto<usize>")]
+    #[serde(default)]
+    pub mpv: MultiPv,
+    #[serde(default)]
+    pub variant: LilaVariant,
+}
+
+/// Payload of an `evalPut` message from lila-ws, submitting analysis of a
+/// client.
+#[serde_as]
+#[derive(Deserialize)]
+pub struct EvalPut {
+    #[serde_as(as = "DisplayFromStr")]
+    pub fen: Fen,
+    pub knodes: u64,
+    pub depth: u32,
+    pub pvs: Vec<Pv>,
+    #[serde(default)]
+    pub variant: LilaVariant,
+}
+
+impl EvalPut {
+    /// Sets up the position and validates the submitted analysis.
+    pub fn into_user_eval(
+        self,
+        submitted_at: SystemTime,
+    ) -> Result<(VariantPosition, UserEval), Error> {
+        MultiPv::try_from(self.pvs.len())?;
+        let pos = self.variant.position(self.fen)?;
+        let mut user_eval = UserEval {
+            pvs: self.pvs,
+            depth: self.depth,
+            knodes: self.knodes,
+            submitted_at,
+        };
+        user_eval.validate(&pos)?;
+        Ok((pos, user_eval))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "t", content = "d", rename_all = "camelCase")]
+pub enum ClientMessage {
+    EvalGet(EvalGet),
+    EvalPut(EvalPut),
+}
+
+#[derive(Serialize)]
+#[serde(tag = "t", content = "d", rename_all = "camelCase")]
+pub enum ServerMessage {
+    EvalHit(EvalHit),
+}
@@ -0,0 +1,157 @@
+use std::{cmp::Ordering, ops::Neg};
+
+// chessdb.cn packs mate and tablebase results into the same i16 as
+// centipawn scores, counting down from fixed bases.
+const MATE_BASE: i32 = 30000;
+const TB_WIN_BASE: i32 = 25000;
+const CURSED_WIN_BASE: i32 = 20000;
+const MAX_DISTANCE: i32 = 1000;
+const MAX_CP: i16 = (CURSED_WIN_BASE - MAX_DISTANCE) as i16;
+
+/// Score from the point of view of the side to move.
+///
+/// Distances are given in plies.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Score {
+    Cp(i16),
+    /// Side to move mates in the given number of plies.
+    MateIn(u16),
+    /// Side to move gets mated in the given number of plies.
+    MatedIn(u16),
+    /// Tablebase win.
+    TbWin(u16),
+    /// Tablebase loss.
+    TbLoss(u16),
+    /// Tablebase win that is a draw under the 50-move rule.
+    CursedWin(u16),
+    /// Tablebase loss that is a draw under the 50-move rule.
+    BlessedLoss(u16),
+}
+
+impl Score {
+    /// The worst possible score.
+    pub const MIN: Score = Score::MatedIn(0);
+
+    /// The best possible score.
+    pub const MAX: Score = Score::MateIn(0);
+
+    /// Decode a score in the chessdb.cn convention.
+    pub fn from_cdb(value: i16) -> Score {
+        let abs = i32::from(value).abs();
+        let win = value > 0;
+
+        let distance = |base: i32| u16::try_from((base - abs).max(0)).expect("distance");
+
+        if abs > MATE_BASE - MAX_DISTANCE {
+            let plies = distance(MATE_BASE);
+            if win {
+                Score::MateIn(plies)
+            } else {
+                Score::MatedIn(plies)
+            }
+        } else if abs > TB_WIN_BASE - MAX_DISTANCE && abs <= TB_WIN_BASE {
+            let plies = distance(TB_WIN_BASE);
+            if win {
+                Score::TbWin(plies)
+            } else {
+                Score::TbLoss(plies)
+            }
+        } else if abs > CURSED_WIN_BASE - MAX_DISTANCE && abs <= CURSED_WIN_BASE {
+            let plies = distance(CURSED_WIN_BASE);
+            if win {
+                Score::CursedWin(plies)
+            } else {
+                Score::BlessedLoss(plies)
+            }
+        } else {
+            Score::Cp(value)
+        }
+    }
+
+    /// Encode the score in the chessdb.cn convention.
+    ///
+    /// Centipawns and distances are saturated, so that the result always
+    /// decodes to the same kind of score.
+    pub fn to_cdb(self) -> i16 {
+        let encode = |base: i32, plies: u16| {
+            i16::try_from(base - i32::from(plies).min(MAX_DISTANCE - 1)).expect("encoded score")
+        };
+
+        match self {
+            Score::Cp(cp) => cp.clamp(-MAX_CP, MAX_CP),
+            Score::MateIn(plies) => encode(MATE_BASE, plies),
+            Score::MatedIn(plies) => -encode(MATE_BASE, plies),
+            Score::TbWin(plies) => encode(TB_WIN_BASE, plies),
+            Score::TbLoss(plies) => -encode(TB_WIN_BASE, plies),
+            Score::CursedWin(plies) => encode(CURSED_WIN_BASE, plies),
+            Score::BlessedLoss(plies) => -encode(CURSED_WIN_BASE, plies),
+        }
+    }
+
+    /// Encode the score in the chessdb.cn convention, like
+    /// [`Score::to_cdb()`], but keep centipawns beyond the saturation limit
+    /// if they decode to the same score. Every value accepted by
+    /// [`ScoredMoves::try_read_cdb()`](crate::cdb_moves::ScoredMoves::try_read_cdb)
+    /// is re-encoded to the same bytes.
+    pub fn to_cdb_exact(self) -> i16 {
+        match self {
+            Score::Cp(cp) if Score::from_cdb(cp) == self => cp,
+            _ => self.to_cdb(),
+        }
+    }
+
+    pub fn is_mate(self) -> bool {
+        matches!(self, Score::MateIn(_) | Score::MatedIn(_))
+    }
+
+    /// Number of moves (not plies) until mate, in the convention of UCI and
+    /// lila: Positive if the side to move is delivering mate.
+    pub fn mate_moves(self) -> Option<i32> {
+        match self {
+            Score::MateIn(plies) => Some((i32::from(plies) + 1) / 2),
+            Score::MatedIn(plies) => Some(-(i32::from(plies) + 1) / 2),
+            _ => None,
+        }
+    }
+}
+
+impl Neg for Score {
+    type Output = Score;
+
+    fn neg(self) -> Score {
+        match self {
+            Score::Cp(cp) => Score::Cp(cp.saturating_neg()),
+            Score::MateIn(plies) => Score::MatedIn(plies),
+            Score::MatedIn(plies) => Score::MateIn(plies),
+            Score::TbWin(plies) => Score::TbLoss(plies),
+            Score::TbLoss(plies) => Score::TbWin(plies),
+            Score::CursedWin(plies) => Score::BlessedLoss(plies),
+            Score::BlessedLoss(plies) => Score::CursedWin(plies),
+        }
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Score) -> Ordering {
+        // The encoding is monotonic, with the exception of saturated
+        // centipawns and distances.
+        self.to_cdb()
+            .cmp(&other.to_cdb())
+            .then_with(|| match (*self, *other) {
+                (Score::Cp(a), Score::Cp(b)) => a.cmp(&b),
+                (Score::MateIn(a), Score::MateIn(b))
+                | (Score::TbWin(a), Score::TbWin(b))
+                | (Score::CursedWin(a), Score::CursedWin(b)) => b.cmp(&a),
+                (Score::MatedIn(a), Score::MatedIn(b))
+                | (Score::TbLoss(a), Score::TbLoss(b))
+                | (Score::BlessedLoss(a), Score::BlessedLoss(b)) => a.cmp(&b),
+                _ => Ordering::Equal,
+            })
+    }
+}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Score) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
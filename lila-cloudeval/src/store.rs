@@ -0,0 +1,106 @@
+use std::{collections::BTreeMap, ops::Deref};
+
+use shakmaty::Setup;
+use terarkdb::{Db, MallocedBytes, ReadOptions};
+
+use crate::{
+    cdb_fen::cdb_fen,
+    cdb_moves::{CdbEncodeError, ScoredMoves},
+    error::Error,
+};
+
+/// Lookup of chessdb.cn values by [`cdb_fen()`] key.
+///
+/// All lookups through the same store are expected to observe the same
+/// state of the underlying data.
+pub trait PositionStore {
+    type Value<'a>: Deref<Target = [u8]>
+    where
+        Self: 'a;
+
+    fn get(&self, key: &[u8]) -> Result<Option<Self::Value<'_>>, Error>;
+
+    fn multi_get<K: AsRef<[u8]>>(&self, keys: &[K]) -> Vec<Result<Option<Self::Value<'_>>, Error>>;
+}
+
+/// Terarkdb database, read with fixed read options (usually pinned to a
+/// snapshot).
+pub struct TerarkdbStore<'a> {
+    db: &'a Db,
+    read_options: &'a ReadOptions<'a>,
+}
+
+impl<'a> TerarkdbStore<'a> {
+    pub fn new(db: &'a Db, read_options: &'a ReadOptions<'a>) -> TerarkdbStore<'a> {
+        TerarkdbStore { db, read_options }
+    }
+}
+
+impl PositionStore for TerarkdbStore<'_> {
+    type Value<'a>
+        = MallocedBytes
+    where
+        Self: 'a;
+
+    fn get(&self, key: &[u8]) -> Result<Option<MallocedBytes>, Error> {
+        Ok(self.db.get_opt(key, self.read_options)?)
+    }
+
+    fn multi_get<K: AsRef<[u8]>>(&self, keys: &[K]) -> Vec<Result<Option<MallocedBytes>, Error>> {
+        self.db
+            .multi_get_opt(keys, self.read_options)
+            .into_iter()
+            .map(|row| row.map_err(Error::from))
+            .collect()
+    }
+}
+
+/// In-memory store, mostly for testing.
+#[derive(Default, Debug, Clone)]
+pub struct MemoryStore {
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn insert<K: Into<Vec<u8>>, V: Into<Vec<u8>>>(&mut self, key: K, value: V) {
+        self.entries.insert(key.into(), value.into());
+    }
+
+    /// Encodes and inserts the scored moves of a position, just like
+    /// chessdb.cn would store them.
+    pub fn insert_scored_moves(
+        &mut self,
+        setup: &Setup,
+        scored_moves: &ScoredMoves,
+    ) -> Result<(), CdbEncodeError> {
+        let (key, natural_order) = cdb_fen(setup);
+        let mut value = Vec::new();
+        scored_moves.write_cdb(&mut value, natural_order)?;
+        self.entries.insert(key.into_bytes(), value);
+        Ok(())
+    }
+}
+
+impl PositionStore for MemoryStore {
+    type Value<'a> = &'a [u8];
+
+    fn get(&self, key: &[u8]) -> Result<Option<&[u8]>, Error> {
+        Ok(self.entries.get(key).map(Vec::as_slice))
+    }
+
+    fn multi_get<K: AsRef<[u8]>>(&self, keys: &[K]) -> Vec<Result<Option<&[u8]>, Error>> {
+        keys.iter().map(|key| self.get(key.as_ref())).collect()
+    }
+}
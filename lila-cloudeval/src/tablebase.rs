@@ -0,0 +1,178 @@
+use std::{cmp::Reverse, io, path::Path};
+
+use shakmaty::{uci::UciMove, Chess, Move, Position};
+use shakmaty_syzygy::{Dtz, SyzygyError, Tablebase};
+
+use crate::{
+    cdb_moves::RelativeScore,
+    database::{Analysis, Pv, PvBudget, WhiteScore},
+    error::Error,
+    score::Score,
+};
+
+/// Maximum length of tablebase pvs. Lines that minimize the distance to
+/// zeroing can be very long, and do not necessarily lead to the quickest
+/// mate.
+const MAX_PV_PLIES: usize = 40;
+
+/// Syzygy tablebases for standard chess.
+#[derive(Debug)]
+pub struct Tablebases {
+    tables: Tablebase<Chess>,
+}
+
+impl Tablebases {
+    pub fn open<P: AsRef<Path>>(paths: &[P]) -> io::Result<Tablebases> {
+        let mut tables = Tablebase::new();
+        for path in paths {
+            tables.add_directory(path)?;
+        }
+        Ok(Tablebases { tables })
+    }
+
+    /// Whether the position is within the piece limit of the tablebases.
+    /// Positions with castling rights are never covered.
+    pub fn covers(&self, pos: &Chess) -> bool {
+        pos.board().occupied().count() <= self.tables.max_pieces()
+            && pos.castles().castling_rights().is_empty()
+    }
+
+    /// Whether the position is covered and its table is available.
+    pub fn can_score(&self, pos: &Chess) -> Result<bool, Error> {
+        Ok(self.covers(pos) && self.score(pos)?.is_some())
+    }
+
+    /// Extracts up to `multi_pv` tablebase-perfect principal variations, or
+    /// `None` if the position is not covered, a table is missing, or there
+    /// are no legal moves. Each ply probes all moves of the position, which
+    /// counts against the lookups of the budget.
+    pub fn get_multi_pv(
+        &self,
+        pos: &Chess,
+        multi_pv: usize,
+        budget: &PvBudget,
+    ) -> Result<Option<Analysis>, Error> {
+        if !self.covers(pos) {
+            return Ok(None);
+        }
+
+        let budget = budget.start();
+        let max_plies = budget.max_plies.min(MAX_PV_PLIES);
+        let mut lookups = 0;
+
+        let Some(moves) = self.scored_moves(pos)? else {
+            return Ok(None); // Missing table
+        };
+        if moves.is_empty() {
+            return Ok(None); // Checkmate or stalemate
+        }
+
+        let mut pvs = Vec::with_capacity(multi_pv);
+        for (m, score) in moves.into_iter().take(multi_pv) {
+            let mut child = pos.clone();
+            let mut line = vec![UciMove::from_chess960(&m)];
+            child.play_unchecked(&m);
+
+            let mut truncated = false;
+            loop {
+                lookups += child.legal_moves().len();
+                if lookups > budget.max_lookups || budget.is_expired() {
+                    truncated = !child.is_game_over();
+                    break;
+                }
+
+                let Some((m, _)) = self
+                    .scored_moves(&child)?
+                    .and_then(|moves| moves.into_iter().next())
+                else {
+                    break;
+                };
+                if line.len() >= max_plies {
+                    truncated = true;
+                    break;
+                }
+                line.push(UciMove::from_chess960(&m));
+                child.play_unchecked(&m);
+            }
+
+            pvs.push(Pv {
+                score: WhiteScore::from_relative(RelativeScore(score), pos.turn()),
+                moves: line,
+                truncated,
+            });
+        }
+
+        Ok(Some(Analysis {
+            pvs,
+            ply_from_root: None,
+        }))
+    }
+
+    /// All legal moves with their scores, from best to worst, or `None` if
+    /// a table is missing.
+    fn scored_moves(&self, pos: &Chess) -> Result<Option<Vec<(Move, Score)>>, Error> {
+        let mut moves = Vec::new();
+        for m in pos.legal_moves() {
+            let mut child = pos.clone();
+            child.play_unchecked(&m);
+            let Some(score) = self.score(&child)? else {
+                return Ok(None);
+            };
+            let score = backup(score, &m);
+            moves.push((m, score));
+        }
+        moves.sort_by_key(|(_, score)| Reverse(*score));
+        Ok(Some(moves))
+    }
+
+    /// Score of the position from the point of view of the side to move, or
+    /// `None` if the table is missing.
+    fn score(&self, pos: &Chess) -> Result<Option<Score>, Error> {
+        if pos.is_checkmate() {
+            return Ok(Some(Score::MatedIn(0)));
+        }
+        if pos.is_stalemate() || pos.is_insufficient_material() {
+            return Ok(Some(Score::Cp(0)));
+        }
+
+        let Dtz(dtz) = match self.tables.probe_dtz(pos) {
+            Ok(dtz) => dtz.ignore_rounding(),
+            Err(SyzygyError::MissingTable { .. }) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        // Wins and losses are only real if zeroing is possible before the
+        // 50-move rule kicks in.
+        let plies = u16::try_from(dtz.unsigned_abs()).unwrap_or(u16::MAX);
+        let cursed = dtz.unsigned_abs() + pos.halfmoves() > 100;
+        Ok(Some(match (dtz.signum(), cursed) {
+            (1, false) => Score::TbWin(plies),
+            (1, true) => Score::CursedWin(plies),
+            (-1, false) => Score::TbLoss(plies),
+            (-1, true) => Score::BlessedLoss(plies),
+            _ => Score::Cp(0),
+        }))
+    }
+}
+
+/// Score of a move, given the score of the resulting position.
+fn backup(child: Score, m: &Move) -> Score {
+    // The distance to zeroing restarts after captures and pawn moves.
+    let dtz = |plies: u16| {
+        if m.is_zeroing() {
+            1
+        } else {
+            plies.saturating_add(1)
+        }
+    };
+
+    match -child {
+        Score::Cp(cp) => Score::Cp(cp),
+        Score::MateIn(plies) => Score::MateIn(plies.saturating_add(1)),
+        Score::MatedIn(plies) => Score::MatedIn(plies.saturating_add(1)),
+        Score::TbWin(plies) => Score::TbWin(dtz(plies)),
+        Score::TbLoss(plies) => Score::TbLoss(dtz(plies)),
+        Score::CursedWin(plies) => Score::CursedWin(dtz(plies)),
+        Score::BlessedLoss(plies) => Score::BlessedLoss(dtz(plies)),
+    }
+}
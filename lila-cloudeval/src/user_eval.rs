@@ -0,0 +1,277 @@
+use std::time::{Duration, SystemTime};
+
+use bytes::{Buf, BufMut};
+use shakmaty::{uci::UciMove, variant::Variant, Color, Position, Setup};
+use thiserror::Error;
+
+use crate::{
+    binary_fen::VariantSetup,
+    database::{Pv, WhiteScore},
+};
+
+const FORMAT_VERSION: u8 = 0;
+
+/// Minimum depth of submitted analysis.
+pub const MIN_DEPTH: u32 = 20;
+
+/// Minimum number of nodes (in thousands) of submitted analysis.
+pub const MIN_KNODES: u64 = 10_000;
+
+/// Maximum length of submitted pvs, well within what the encoding can hold.
+pub const MAX_PV_PLIES: usize = 1000;
+
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum UserEvalDecodeError {
+    #[error("unexpected end of user eval")]
+    Truncated,
+    #[error("unknown user eval format version: {version}")]
+    UnknownVersion { version: u8 },
+    #[error("invalid score tag in user eval: {tag}")]
+    InvalidScore { tag: u8 },
+    #[error("invalid move in user eval")]
+    InvalidMove,
+    #[error("trailing bytes after user eval")]
+    TrailingBytes,
+}
+
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum UserEvalEncodeError {
+    #[error("too many pvs for user eval: {n}")]
+    TooManyPvs { n: usize },
+    #[error("pv {index} too long for user eval")]
+    PvTooLong { index: usize },
+}
+
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum InvalidUserEval {
+    #[error("depth {depth} is below the minimum of {min}", min = MIN_DEPTH)]
+    DepthTooLow { depth: u32 },
+    #[error("knodes {knodes} is below the minimum of {min}", min = MIN_KNODES)]
+    KnodesTooLow { knodes: u64 },
+    #[error("no pvs")]
+    NoPvs,
+    #[error("pv {index} is empty")]
+    EmptyPv { index: usize },
+    #[error("pv {index} has {plies} plies, but only {max} allowed", max = MAX_PV_PLIES)]
+    PvTooLong { index: usize, plies: usize },
+    #[error("illegal move {uci} in pv {index}")]
+    IllegalMove { index: usize, uci: UciMove },
+    #[error("pv {index} starts with the same move as an earlier pv")]
+    DuplicateFirstMove { index: usize },
+    #[error("pv {index} is scored better than an earlier pv")]
+    UnsortedPvs { index: usize },
+}
+
+/// Analysis provided by a user, for example from a live broadcast or a
+/// shared study.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UserEval {
+    pub pvs: Vec<Pv>,
+    pub depth: u32,
+    pub knodes: u64,
+    pub submitted_at: SystemTime,
+}
+
+/// Database key of the user eval for a position.
+pub fn user_eval_key(setup: Setup, variant: Variant) -> Vec<u8> {
+    let mut key = Vec::new();
+    VariantSetup::new_normalized(setup, variant).write(&mut key);
+    key
+}
+
+/// Sort key for scores from the point of view of the side to move. Higher
+/// is better.
+fn score_key(score: WhiteScore, turn: Color) -> (u8, i32) {
+    let key = match score {
+        WhiteScore::Mate(moves) if moves > 0 => (2, -moves),
+        WhiteScore::Cp(cp) => (1, i32::from(cp)),
+        WhiteScore::Mate(moves) => (0, -moves),
+    };
+    match turn {
+        Color::White => key,
+        Color::Black => (2 - key.0, -key.1),
+    }
+}
+
+fn ensure_remaining<B: Buf>(buf: &B, n: usize) -> Result<(), UserEvalDecodeError> {
+    if buf.remaining() >= n {
+        Ok(())
+    } else {
+        Err(UserEvalDecodeError::Truncated)
+    }
+}
+
+impl UserEval {
+    pub fn multi_pv(&self) -> usize {
+        self.pvs.len()
+    }
+
+    /// Checks that the analysis is plausible for the given position, and
+    /// rewrites all moves in the notation used by chessdb.cn (king moves to
+    /// rook for castling).
+    ///
+    /// Every pv must be legal and start with a distinct move, and pvs must be
+    /// ordered from best to worst.
+    pub fn validate<P: Position + Clone>(&mut self, pos: &P) -> Result<(), InvalidUserEval> {
+        if self.depth < MIN_DEPTH {
+            return Err(InvalidUserEval::DepthTooLow { depth: self.depth });
+        }
+        if self.knodes < MIN_KNODES {
+            return Err(InvalidUserEval::KnodesTooLow {
+                knodes: self.knodes,
+            });
+        }
+        if self.pvs.is_empty() {
+            return Err(InvalidUserEval::NoPvs);
+        }
+
+        for (index, pv) in self.pvs.iter_mut().enumerate() {
+            if pv.moves.is_empty() {
+                return Err(InvalidUserEval::EmptyPv { index });
+            }
+            if pv.moves.len() > MAX_PV_PLIES {
+                return Err(InvalidUserEval::PvTooLong {
+                    index,
+                    plies: pv.moves.len(),
+                });
+            }
+
+            let mut pos = pos.clone();
+            for uci in &mut pv.moves {
+                let m = uci
+                    .to_move(&pos)
+                    .map_err(|_| InvalidUserEval::IllegalMove {
+                        index,
+                        uci: uci.clone(),
+                    })?;
+                *uci = UciMove::from_chess960(&m);
+                pos.play_unchecked(&m);
+            }
+        }
+
+        for index in 1..self.pvs.len() {
+            let pv = &self.pvs[index];
+            if score_key(self.pvs[index - 1].score, pos.turn()) < score_key(pv.score, pos.turn()) {
+                return Err(InvalidUserEval::UnsortedPvs { index });
+            }
+            if self.pvs[..index]
+                .iter()
+                .any(|earlier| earlier.moves[0] == pv.moves[0])
+            {
+                return Err(InvalidUserEval::DuplicateFirstMove { index });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the user eval, the inverse of [`UserEval::try_read()`].
+    /// Nothing is written on error.
+    pub fn write<B: BufMut>(&self, buf: &mut B) -> Result<(), UserEvalEncodeError> {
+        let num_pvs = u8::try_from(self.pvs.len())
+            .map_err(|_| UserEvalEncodeError::TooManyPvs { n: self.pvs.len() })?;
+        let pv_moves = self
+            .pvs
+            .iter()
+            .enumerate()
+            .map(|(index, pv)| {
+                let moves = pv
+                    .moves
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let len = u16::try_from(moves.len())
+                    .map_err(|_| UserEvalEncodeError::PvTooLong { index })?;
+                Ok((len, moves))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        buf.put_u8(FORMAT_VERSION);
+        buf.put_u32(self.depth);
+        buf.put_u64(self.knodes);
+        buf.put_u64(
+            self.submitted_at
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        );
+
+        buf.put_u8(num_pvs);
+        for (pv, (len, moves)) in self.pvs.iter().zip(pv_moves) {
+            match pv.score {
+                WhiteScore::Cp(cp) => {
+                    buf.put_u8(0);
+                    buf.put_i16(cp);
+                }
+                WhiteScore::Mate(mate) => {
+                    buf.put_u8(1);
+                    buf.put_i32(mate);
+                }
+            }
+
+            buf.put_u16(len);
+            buf.put_slice(moves.as_bytes());
+        }
+
+        Ok(())
+    }
+
+    pub fn try_read<B: Buf>(buf: &mut B) -> Result<UserEval, UserEvalDecodeError> {
+        ensure_remaining(buf, 1)?;
+        let version = buf.get_u8();
+        if version != FORMAT_VERSION {
+            return Err(UserEvalDecodeError::UnknownVersion { version });
+        }
+
+        ensure_remaining(buf, 4 + 8 + 8 + 1)?;
+        let depth = buf.get_u32();
+        let knodes = buf.get_u64();
+        let submitted_at = SystemTime::UNIX_EPOCH + Duration::from_secs(buf.get_u64());
+
+        let num_pvs = buf.get_u8();
+        let mut pvs = Vec::with_capacity(usize::from(num_pvs));
+        for _ in 0..num_pvs {
+            ensure_remaining(buf, 1)?;
+            let score = match buf.get_u8() {
+                0 => {
+                    ensure_remaining(buf, 2)?;
+                    WhiteScore::Cp(buf.get_i16())
+                }
+                1 => {
+                    ensure_remaining(buf, 4)?;
+                    WhiteScore::Mate(buf.get_i32())
+                }
+                tag => return Err(UserEvalDecodeError::InvalidScore { tag }),
+            };
+
+            ensure_remaining(buf, 2)?;
+            let len = usize::from(buf.get_u16());
+            ensure_remaining(buf, len)?;
+            let mut moves_bytes = vec![0; len];
+            buf.copy_to_slice(&mut moves_bytes);
+            let moves = moves_bytes
+                .split(|&b| b == b' ')
+                .filter(|uci| !uci.is_empty())
+                .map(UciMove::from_ascii)
+                .collect::<Result<_, _>>()
+                .map_err(|_| UserEvalDecodeError::InvalidMove)?;
+
+            pvs.push(Pv {
+                score,
+                moves,
+                truncated: false,
+            });
+        }
+
+        if buf.has_remaining() {
+            return Err(UserEvalDecodeError::TrailingBytes);
+        }
+
+        Ok(UserEval {
+            pvs,
+            depth,
+            knodes,
+            submitted_at,
+        })
+    }
+}
@@ -0,0 +1,81 @@
+use lila_cloudeval::binary_fen::{BinaryFenError, VariantSetup};
+use shakmaty::{fen::Fen, variant::Variant, Setup};
+
+fn write(setup: Setup, variant: Variant) -> Vec<u8> {
+    let mut buf = Vec::new();
+    VariantSetup::new_normalized(setup, variant).write(&mut buf);
+    buf
+}
+
+#[test]
+fn test_try_read_roundtrip() {
+    for (fen, variant) in [
+        (
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            Variant::Chess,
+        ),
+        (
+            "r1bqkbnr/pppp1ppp/2n5/1B2p3/4P3/5N2/PPPP1PPP/RNBQK2R b KQkq - 3 3",
+            Variant::Atomic,
+        ),
+        (
+            "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2 +1+0",
+            Variant::ThreeCheck,
+        ),
+        (
+            "r~1bqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR[Pn] b KQkq - 0 10",
+            Variant::Crazyhouse,
+        ),
+    ] {
+        let setup = fen.parse::<Fen>().expect("fen").into_setup();
+        let buf = write(setup.clone(), variant);
+        assert_eq!(
+            VariantSetup::try_read(&mut &buf[..]),
+            Ok(VariantSetup::new_normalized(setup, variant)),
+            "{fen}"
+        );
+    }
+}
+
+#[test]
+fn test_try_read_errors() {
+    let initial = write(Setup::initial(), Variant::Chess);
+
+    assert_eq!(
+        VariantSetup::try_read(&mut &[][..]),
+        Err(BinaryFenError::Truncated)
+    );
+    assert_eq!(
+        VariantSetup::try_read(&mut &initial[..initial.len() - 1]),
+        Err(BinaryFenError::Truncated)
+    );
+
+    let mut bad_variant = initial.clone();
+    bad_variant.extend_from_slice(&[0, 0, 42]);
+    assert_eq!(
+        VariantSetup::try_read(&mut &bad_variant[..]),
+        Err(BinaryFenError::InvalidVariant { header: 42 })
+    );
+
+    let mut trailing = initial.clone();
+    trailing.extend_from_slice(&[0, 0, 0, 0]);
+    assert_eq!(
+        VariantSetup::try_read(&mut &trailing[..]),
+        Err(BinaryFenError::TrailingBytes)
+    );
+
+    let mut overlong = initial.clone();
+    overlong.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0xff, 0x01]);
+    assert_eq!(
+        VariantSetup::try_read(&mut &overlong[..]),
+        Err(BinaryFenError::OverlongLeb128)
+    );
+
+    let three_check = write(Setup::initial(), Variant::ThreeCheck);
+    let mut bad_checks = three_check.clone();
+    *bad_checks.last_mut().unwrap() = 0x47;
+    assert_eq!(
+        VariantSetup::try_read(&mut &bad_checks[..]),
+        Err(BinaryFenError::InvalidNibble { nibble: 7 })
+    );
+}
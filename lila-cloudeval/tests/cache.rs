@@ -0,0 +1,189 @@
+use std::{num::NonZeroUsize, sync::Arc};
+
+use futures_util::poll;
+use lila_cloudeval::{
+    cache::{AnalysisCache, CacheKey, Claim, Lookup},
+    database::{Analysis, Pv, WhiteScore},
+};
+use shakmaty::zobrist::Zobrist64;
+
+fn analysis(multi_pv: usize) -> Analysis {
+    Analysis {
+        pvs: ["e2e4", "d2d4", "g1f3", "c2c4", "b1c3"]
+            .into_iter()
+            .take(multi_pv)
+            .map(|uci| Pv {
+                score: WhiteScore::Cp(20),
+                moves: vec![uci.parse().expect("uci")],
+                truncated: false,
+            })
+            .collect(),
+        ply_from_root: Some(0),
+    }
+}
+
+fn key(hash: u64, multi_pv: usize) -> CacheKey {
+    CacheKey {
+        hash: Zobrist64(hash),
+        multi_pv,
+        max_plies: usize::MAX,
+    }
+}
+
+fn claim(lookup: Lookup) -> Claim {
+    let Lookup::Claimed(claim) = lookup else {
+        panic!("expected claim, got {lookup:?}");
+    };
+    claim
+}
+
+fn first_moves(lookup: Lookup) -> Vec<String> {
+    let Lookup::Hit(Some(analysis)) = lookup else {
+        panic!("expected hit, got {lookup:?}");
+    };
+    analysis
+        .pvs
+        .iter()
+        .map(|pv| pv.moves[0].to_string())
+        .collect()
+}
+
+#[test]
+fn test_multi_pv() {
+    let cache = AnalysisCache::new(NonZeroUsize::new(2).expect("non-zero"));
+
+    let claimed = claim(cache.lookup(key(1, 3)));
+    assert!(matches!(cache.lookup(key(1, 2)), Lookup::Miss));
+    cache.insert(key(1, 3), &Some(analysis(3)));
+    cache.release(claimed);
+
+    // Fewer pvs are served from the cached result.
+    assert_eq!(first_moves(cache.lookup(key(1, 2))), ["e2e4", "d2d4"]);
+    assert_eq!(
+        first_moves(cache.lookup(key(1, 3))),
+        ["e2e4", "d2d4", "g1f3"]
+    );
+
+    // More pvs are not.
+    cache.release(claim(cache.lookup(key(1, 4))));
+
+    // Not enough moves for fewer pvs means not enough moves for more pvs.
+    let claimed = claim(cache.lookup(key(2, 2)));
+    cache.insert(key(2, 2), &None);
+    cache.release(claimed);
+    assert!(matches!(cache.lookup(key(2, 3)), Lookup::Hit(None)));
+    cache.release(claim(cache.lookup(key(2, 1))));
+
+    assert_eq!(cache.hits(), 3);
+    assert_eq!(cache.misses(), 5);
+    assert_eq!(cache.len(), 2);
+}
+
+#[test]
+fn test_eviction() {
+    let cache = AnalysisCache::new(NonZeroUsize::new(2).expect("non-zero"));
+    for hash in 1..=3 {
+        cache.insert(key(hash, 1), &Some(analysis(1)));
+    }
+    assert_eq!(cache.len(), 2);
+    assert!(matches!(cache.lookup(key(1, 1)), Lookup::Claimed(_)));
+    assert!(matches!(cache.lookup(key(3, 1)), Lookup::Hit(_)));
+}
+
+#[test]
+fn test_max_plies() {
+    let cache = AnalysisCache::new(NonZeroUsize::new(16).expect("non-zero"));
+    let pv = |moves: &str, truncated| Pv {
+        score: WhiteScore::Cp(20),
+        moves: moves
+            .split(' ')
+            .map(|uci| uci.parse().expect("uci"))
+            .collect(),
+        truncated,
+    };
+    let with_max_plies = |hash, max_plies| CacheKey {
+        max_plies,
+        ..key(hash, 2)
+    };
+
+    // Truncated by max_plies.
+    cache.insert(
+        with_max_plies(1, 3),
+        &Some(Analysis {
+            pvs: vec![pv("e2e4 e7e5 g1f3", true), pv("d2d4 d7d5", false)],
+            ply_from_root: None,
+        }),
+    );
+    let Lookup::Hit(Some(shorter)) = cache.lookup(with_max_plies(1, 2)) else {
+        panic!("expected hit");
+    };
+    assert_eq!(shorter.pvs, [pv("e2e4 e7e5", true), pv("d2d4 d7d5", false)]);
+    assert!(matches!(
+        cache.lookup(with_max_plies(1, 4)),
+        Lookup::Claimed(_)
+    ));
+
+    // Truncated by a deadline.
+    cache.insert(
+        with_max_plies(2, 3),
+        &Some(Analysis {
+            pvs: vec![pv("e2e4 e7e5", true), pv("d2d4 d7d5", false)],
+            ply_from_root: None,
+        }),
+    );
+    assert!(matches!(
+        cache.lookup(with_max_plies(2, 3)),
+        Lookup::Claimed(_)
+    ));
+
+    // Not truncated.
+    cache.insert(with_max_plies(3, 3), &Some(analysis(2)));
+    assert!(matches!(
+        cache.lookup(with_max_plies(3, 100)),
+        Lookup::Hit(Some(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_flight() {
+    let cache = Arc::new(AnalysisCache::new(NonZeroUsize::new(16).expect("non-zero")));
+    let claimed = claim(cache.lookup(key(42, 2)));
+
+    // The analysis in flight can not answer requests for more pvs.
+    assert!(cache.flight(key(42, 3)).is_none());
+
+    let waiters: Vec<_> = (0..4)
+        .map(|_| {
+            let cache = Arc::clone(&cache);
+            let flight = cache.flight(key(42, 1)).expect("in flight");
+            tokio::spawn(async move {
+                flight.wait().await;
+                first_moves(cache.lookup(key(42, 1)))
+            })
+        })
+        .collect();
+
+    cache.insert(key(42, 2), &Some(analysis(2)));
+    cache.release(claimed);
+    for waiter in waiters {
+        assert_eq!(waiter.await.expect("waiter"), ["e2e4"]);
+    }
+    assert_eq!(cache.misses(), 1);
+    assert_eq!(cache.hits(), 4);
+    assert!(cache.flight(key(42, 1)).is_none());
+}
+
+#[tokio::test]
+async fn test_release_without_result() {
+    let cache = AnalysisCache::new(NonZeroUsize::new(16).expect("non-zero"));
+    let claimed = claim(cache.lookup(key(42, 1)));
+
+    let wait = cache.flight(key(42, 1)).expect("in flight").wait();
+    tokio::pin!(wait);
+    assert!(poll!(&mut wait).is_pending());
+
+    cache.release(claimed);
+    wait.await;
+    assert!(matches!(cache.lookup(key(42, 1)), Lookup::Claimed(_)));
+    assert!(matches!(cache.lookup(key(42, 1)), Lookup::Miss));
+}
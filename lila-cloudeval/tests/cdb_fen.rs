@@ -1,9 +1,9 @@
 use std::{cmp::min, fs::File};
 
-use lila_cloudeval::cdb_fen::cdb_fen;
+use lila_cloudeval::cdb_fen::{cdb_fen, parse_cdb_fen, CdbFenError, NaturalOrder};
 use serde::Deserialize;
 use serde_with::{serde_as, DisplayFromStr};
-use shakmaty::{fen::Fen, CastlingMode, Chess};
+use shakmaty::{fen::Fen, CastlingMode, Chess, Setup};
 
 #[serde_as]
 #[derive(Deserialize)]
@@ -14,6 +14,23 @@ struct Record {
     hex_fen_bw: String,
 }
 
+fn assert_roundtrip(setup: &Setup, context: &str) {
+    let (bin_fen, natural_order) = cdb_fen(setup);
+    let parsed = parse_cdb_fen(&bin_fen).expect("parse cdb fen");
+
+    let expected = match natural_order {
+        NaturalOrder::Same => setup.clone(),
+        NaturalOrder::Mirror => setup.clone().into_mirrored(),
+    };
+    assert_eq!(parsed.board, expected.board, "{context}: board");
+    assert_eq!(parsed.turn, expected.turn, "{context}: turn");
+    assert_eq!(
+        parsed.castling_rights, expected.castling_rights,
+        "{context}: castling rights"
+    );
+    assert_eq!(parsed.ep_square, expected.ep_square, "{context}: ep square");
+}
+
 #[test]
 fn test_cdb_fen() {
     let mut reader = csv::ReaderBuilder::new()
@@ -46,5 +63,44 @@ fn test_cdb_fen() {
             line,
             record.fen
         );
+
+        assert_roundtrip(record.fen.as_setup(), &format!("line {line}"));
+    }
+}
+
+#[test]
+fn test_parse_cdb_fen() {
+    for fen in [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "rnbqkbnr/pppp1ppp/8/8/3Pp3/8/PPP1PPPP/RNBQKBNR b KQkq d3 0 2",
+        "r3k2r/8/8/8/8/8/8/R3K2R b Qk - 0 1",
+        "1r2k1r1/8/8/8/8/8/8/1R2K1R1 w GBgb - 0 1",
+        "rr2k3/8/8/8/8/8/8/4K1RR w Hb - 0 1",
+        "8/8/4k3/8/8/8/8/4K3 w - - 0 1",
+        "4k3/8/8/8/8/8/8/4K3 b - - 0 1",
+    ] {
+        let fen: Fen = fen.parse().expect("fen");
+        assert_roundtrip(fen.as_setup(), &fen.to_string());
     }
 }
+
+#[test]
+fn test_parse_cdb_fen_errors() {
+    assert_eq!(parse_cdb_fen(b""), Err(CdbFenError::InvalidPrefix));
+    assert_eq!(parse_cdb_fen(b"x"), Err(CdbFenError::InvalidPrefix));
+    assert_eq!(parse_cdb_fen(b"h"), Err(CdbFenError::Truncated));
+    // Pawns on the eighth rank, then too many empty squares on the first.
+    assert_eq!(
+        parse_cdb_fen(&[b'h', 0xaa, 0xaa, 0xaa, 0xaa, 0x84, 0x84, 0x84, 0x84, 0x84, 0x84, 0x85]),
+        Err(CdbFenError::InvalidBoard)
+    );
+
+    let (bin_fen, _) = cdb_fen(&Setup::initial());
+    let mut trailing = bin_fen.to_vec();
+    trailing.extend_from_slice(&[0x11, 0x11, 0x11]);
+    assert_eq!(parse_cdb_fen(&trailing), Err(CdbFenError::TrailingNibbles));
+    assert_eq!(
+        parse_cdb_fen(&bin_fen.as_bytes()[..bin_fen.as_bytes().len() - 8]),
+        Err(CdbFenError::Truncated)
+    );
+}
@@ -2,7 +2,8 @@ use std::{collections::HashMap, fs::File};
 
 use lila_cloudeval::{
     cdb_fen::NaturalOrder,
-    cdb_moves::{RelativeScore, ScoredMoves},
+    cdb_moves::{CdbDecodeError, CdbEncodeError, RelativeScore, ScoredMove, ScoredMoves},
+    score::Score,
 };
 use shakmaty::uci::UciMove;
 
@@ -38,13 +39,13 @@ fn test_cdb_moves() {
             while let Some(uci) = fields.next() {
                 moves.insert(
                     uci.parse::<UciMove>().expect("uci"),
-                    RelativeScore(
+                    RelativeScore(Score::from_cdb(
                         fields
                             .next()
                             .expect("score")
                             .parse::<i16>()
                             .expect("score int"),
-                    ),
+                    )),
                 );
             }
             moves
@@ -64,5 +65,126 @@ fn test_cdb_moves() {
         );
 
         assert_eq!(actual_moves, expected_moves, "line {line}");
+
+        let mut written = Vec::new();
+        scored_moves
+            .write_cdb(&mut written, natural_order)
+            .expect("write cdb");
+        let rewritten = ScoredMoves::read_cdb(&mut &written[..], natural_order);
+        let rewritten_moves: HashMap<_, _> = rewritten
+            .moves()
+            .iter()
+            .map(|e| (e.uci.clone(), e.score))
+            .collect();
+        assert_eq!(
+            rewritten.ply_from_root(),
+            ply_from_root,
+            "line {line}: rewritten ply from root mismatch"
+        );
+        assert_eq!(rewritten_moves, expected_moves, "line {line}: rewritten");
     }
 }
+
+#[test]
+fn test_try_read_cdb_errors() {
+    let try_read = |value: &[u8]| ScoredMoves::try_read_cdb(&mut &value[..], NaturalOrder::Same);
+
+    // e2e4 (scored 10), followed by a bad record
+    let e2e4 = [0x28, 0x16, 0xf6, 0xff];
+
+    assert!(try_read(&e2e4).is_ok());
+    assert_eq!(
+        try_read(&[&e2e4[..], &[0x28, 0x16]].concat()).unwrap_err(),
+        CdbDecodeError::Truncated { offset: 4 }
+    );
+    assert_eq!(
+        try_read(&[&e2e4[..], &[0x28, 0xff, 0, 0]].concat()).unwrap_err(),
+        CdbDecodeError::InvalidSquare {
+            offset: 4,
+            index: 0xff
+        }
+    );
+    assert_eq!(
+        try_read(&[&e2e4[..], &[0x28, 0x08, 0, 0]].concat()).unwrap_err(),
+        CdbDecodeError::InvalidSquare {
+            offset: 4,
+            index: 0x08
+        }
+    );
+    assert_eq!(
+        try_read(&[&e2e4[..], &[0xa8, 0x16, 0, 0]].concat()).unwrap_err(),
+        CdbDecodeError::InvalidPromotion { offset: 4 }
+    );
+    assert_eq!(
+        try_read(&[&e2e4[..], &[0x28, 0x16, 0x00, 0x80]].concat()).unwrap_err(),
+        CdbDecodeError::InvalidScore {
+            offset: 4,
+            score: i16::MIN
+        }
+    );
+    assert_eq!(
+        try_read(&[&e2e4[..], &[0x00, 0x00, 0xff, 0xff]].concat()).unwrap_err(),
+        CdbDecodeError::InvalidPlyFromRoot {
+            offset: 4,
+            score: -1
+        }
+    );
+}
+
+#[test]
+fn test_write_cdb() {
+    let write = |scored_moves: &ScoredMoves, natural_order| {
+        let mut buf = Vec::new();
+        scored_moves
+            .write_cdb(&mut buf, natural_order)
+            .map(|()| buf)
+    };
+
+    // Ply from root, e2e4 (scored 10), a7a8n (scored -300)
+    let value = [
+        0x00, 0x00, 0x03, 0x00, 0x28, 0x16, 0xf6, 0xff, 0x9b, 0x3f, 0x2c, 0x01,
+    ];
+
+    for natural_order in [NaturalOrder::Same, NaturalOrder::Mirror] {
+        let scored_moves = ScoredMoves::read_cdb(&mut &value[..], natural_order);
+        assert_eq!(scored_moves.ply_from_root(), Some(3));
+        assert_eq!(write(&scored_moves, natural_order).unwrap(), value);
+    }
+
+    let scored_moves = ScoredMoves::read_cdb(&mut &value[..], NaturalOrder::Same);
+    assert_eq!(
+        scored_moves.moves()[1].uci,
+        "a7a8n".parse::<UciMove>().unwrap()
+    );
+    assert_eq!(
+        scored_moves.moves()[1].score,
+        RelativeScore(Score::Cp(-300))
+    );
+
+    // e2e4 (scored 20500), a7a8n (scored -25500): centipawns beyond the
+    // usual range are written back unchanged
+    let value = [0x28, 0x16, 0x14, 0x50, 0x9b, 0x3f, 0x64, 0x9c];
+    let scored_moves = ScoredMoves::read_cdb(&mut &value[..], NaturalOrder::Same);
+    assert_eq!(
+        scored_moves.moves()[0].score,
+        RelativeScore(Score::Cp(-20500))
+    );
+    assert_eq!(write(&scored_moves, NaturalOrder::Same).unwrap(), value);
+
+    let mut scored_moves = ScoredMoves::new();
+    scored_moves.push(ScoredMove {
+        uci: UciMove::Null,
+        score: RelativeScore(Score::Cp(0)),
+    });
+    assert_eq!(
+        write(&scored_moves, NaturalOrder::Same).unwrap_err(),
+        CdbEncodeError::UnsupportedMove { uci: UciMove::Null }
+    );
+
+    let mut scored_moves = ScoredMoves::new();
+    scored_moves.set_ply_from_root(Some(40000));
+    assert_eq!(
+        write(&scored_moves, NaturalOrder::Same).unwrap_err(),
+        CdbEncodeError::PlyFromRootRange { ply: 40000 }
+    );
+}
@@ -0,0 +1,134 @@
+use std::time::SystemTime;
+
+use lila_cloudeval::{
+    database::{Analysis, Pv, WhiteScore},
+    eval::{Eval, EvalQuality, EvalSource},
+    user_eval::UserEval,
+};
+use shakmaty::uci::UciMove;
+
+fn pv(cp: i16, moves: &str) -> Pv {
+    Pv {
+        score: WhiteScore::Cp(cp),
+        moves: moves
+            .split(' ')
+            .map(|uci| uci.parse::<UciMove>().expect("uci"))
+            .collect(),
+        truncated: false,
+    }
+}
+
+fn analysis() -> Analysis {
+    Analysis {
+        pvs: vec![pv(30, "e2e4 e7e5 g1f3"), pv(25, "d2d4 d7d5")],
+        ply_from_root: Some(0),
+    }
+}
+
+fn user_eval(depth: u32, pvs: Vec<Pv>) -> UserEval {
+    UserEval {
+        pvs,
+        depth,
+        knodes: 12345,
+        submitted_at: SystemTime::UNIX_EPOCH,
+    }
+}
+
+#[test]
+fn test_quality() {
+    let eval = Eval::Chessdb(analysis());
+    assert_eq!(
+        eval.quality(2, 20),
+        EvalQuality {
+            satisfies_multi_pv: true,
+            depth: 20,
+            pv_len: 2,
+        }
+    );
+    assert!(!eval.quality(3, 20).satisfies_multi_pv);
+
+    // Positions with fewer legal moves need fewer pvs.
+    assert!(eval.quality(3, 2).satisfies_multi_pv);
+
+    // Satisfying the request beats depth.
+    assert!(
+        EvalQuality {
+            satisfies_multi_pv: true,
+            depth: 1,
+            pv_len: 1,
+        } > EvalQuality {
+            satisfies_multi_pv: false,
+            depth: 99,
+            pv_len: 99,
+        }
+    );
+}
+
+#[test]
+fn test_truncated_depth() {
+    // Pvs cut short by a budget do not lower the depth.
+    let mut analysis = Analysis {
+        pvs: (0..3)
+            .map(|_| pv(0, &["g1f3 g8f6 f3g1 f6g8"; 8].join(" ")))
+            .collect(),
+        ply_from_root: None,
+    };
+    assert_eq!(Eval::Chessdb(analysis.clone()).depth(), 32);
+    analysis.pvs[1].truncate(25);
+    assert_eq!(Eval::Chessdb(analysis.clone()).depth(), 32);
+    analysis.pvs[2].truncate(21);
+    assert_eq!(Eval::Chessdb(analysis.clone()).depth(), 32);
+    assert_eq!(Eval::Chessdb(analysis).quality(3, 20).pv_len, 32);
+}
+
+#[test]
+fn test_choose() {
+    // Deeper user analysis wins.
+    let eval = Eval::choose(
+        Some(analysis()),
+        Some(user_eval(30, vec![pv(20, "e2e4"), pv(10, "g1f3")])),
+        2,
+        20,
+    )
+    .expect("eval");
+    assert_eq!(eval.source(), EvalSource::User);
+    assert_eq!(eval.depth(), 30);
+    assert_eq!(eval.knodes(), 12345);
+
+    // Ties go to chessdb.cn.
+    let eval = Eval::choose(
+        Some(analysis()),
+        Some(user_eval(
+            20,
+            vec![pv(20, "e2e4 e7e5"), pv(10, "g1f3 g8f6")],
+        )),
+        2,
+        20,
+    )
+    .expect("eval");
+    assert_eq!(eval.source(), EvalSource::Chessdb);
+
+    // User analysis with too few pvs is not used.
+    let eval = Eval::choose(
+        Some(analysis()),
+        Some(user_eval(40, vec![pv(20, "e2e4")])),
+        2,
+        20,
+    )
+    .expect("eval");
+    assert_eq!(eval.source(), EvalSource::Chessdb);
+    assert!(Eval::choose(None, Some(user_eval(40, vec![pv(20, "e2e4")])), 2, 20).is_none());
+
+    // User analysis is truncated to the requested number of pvs.
+    let eval = Eval::choose(
+        None,
+        Some(user_eval(40, vec![pv(20, "e2e4"), pv(10, "g1f3")])),
+        1,
+        20,
+    )
+    .expect("eval");
+    assert_eq!(eval.source(), EvalSource::User);
+    assert_eq!(eval.pvs().len(), 1);
+
+    assert!(Eval::choose(None, None, 1, 20).is_none());
+}
@@ -0,0 +1,44 @@
+use std::{num::NonZeroUsize, sync::Arc};
+
+use futures_util::poll;
+use lila_cloudeval::lookup_pool::{LookupPool, Overloaded};
+
+#[tokio::test]
+async fn test_run() {
+    let pool = LookupPool::new(NonZeroUsize::new(2).expect("non-zero"), 4);
+    assert_eq!(pool.run(|| 1 + 1).await, Ok(2));
+}
+
+#[tokio::test]
+async fn test_overloaded() {
+    let pool = Arc::new(LookupPool::new(NonZeroUsize::new(1).expect("non-zero"), 1));
+    let (started_tx, started_rx) = crossbeam_channel::bounded::<()>(0);
+    let (release_tx, release_rx) = crossbeam_channel::bounded::<()>(0);
+
+    // Occupy the only thread.
+    let busy = tokio::spawn({
+        let pool = Arc::clone(&pool);
+        async move {
+            pool.run(move || {
+                started_tx.send(()).expect("started");
+                release_rx.recv().expect("release");
+            })
+            .await
+        }
+    });
+    tokio::task::spawn_blocking(move || started_rx.recv().expect("started"))
+        .await
+        .expect("join");
+
+    // Fill the queue.
+    let queued = pool.run(|| 42);
+    tokio::pin!(queued);
+    assert!(poll!(&mut queued).is_pending());
+
+    // Further jobs are rejected right away.
+    assert_eq!(pool.run(|| 0).await, Err(Overloaded));
+
+    release_tx.send(()).expect("release");
+    assert_eq!(busy.await.expect("join"), Ok(()));
+    assert_eq!(queued.await, Ok(42));
+}
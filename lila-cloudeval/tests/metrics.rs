@@ -0,0 +1,98 @@
+use std::num::NonZeroUsize;
+
+use axum::{
+    body::Body,
+    extract::Query,
+    http::{Request, StatusCode},
+    middleware,
+    routing::get,
+    Json, Router,
+};
+use lila_cloudeval::{
+    cache::{AnalysisCache, CacheKey, Lookup},
+    error::Error,
+    metrics::{observe_failures, METRICS},
+    protocol::MultiPv,
+};
+use serde::Deserialize;
+use serde_with::{serde_as, TryFromInto};
+use shakmaty::zobrist::Zobrist64;
+use tower::ServiceExt as _;
+
+#[test]
+fn test_render() {
+    METRICS.observe_multi_pv(2);
+    METRICS.observe_eval(&Ok(None));
+    METRICS.observe_eval(&Err(Error::Overloaded));
+
+    let text = METRICS.render();
+    assert!(text.contains("cloudeval_requests_total{outcome=\"miss\"} 1\n"));
+    assert!(text.contains("cloudeval_requests_total{outcome=\"overloaded\"} 1\n"));
+    assert!(text.contains("cloudeval_multi_pv_bucket{le=\"2\"} 1\n"));
+    assert!(text.contains("# TYPE cloudeval_pv_extension_seconds histogram\n"));
+}
+
+#[test]
+fn test_cache() {
+    let cache = AnalysisCache::new(NonZeroUsize::new(2).expect("non-zero"));
+    let key = CacheKey {
+        hash: Zobrist64(1),
+        multi_pv: 1,
+        max_plies: usize::MAX,
+    };
+    let Lookup::Claimed(claim) = cache.lookup(key) else {
+        panic!("expected claim");
+    };
+    cache.insert(key, &None);
+    cache.release(claim);
+    assert!(matches!(cache.lookup(key), Lookup::Hit(None)));
+    assert!(matches!(cache.lookup(key), Lookup::Hit(None)));
+
+    METRICS.observe_cache(&cache);
+    let text = METRICS.render();
+    assert!(text.contains("cloudeval_analysis_cache_entries 1\n"));
+    assert!(text.contains("cloudeval_analysis_cache_hits 2\n"));
+    assert!(text.contains("cloudeval_analysis_cache_misses 1\n"));
+}
+
+#[serde_as]
+#[derive(Deserialize)]
+struct MultiPvQuery {
+    #[serde_as(as = "TryFromInto<usize>")]
+    multi_pv: MultiPv,
+}
+
+#[tokio::test]
+async fn test_failures() {
+    let app = Router::new()
+        .route(
+            "/",
+            get(|Query(query): Query<MultiPvQuery>| async move {
+                match usize::from(query.multi_pv) {
+                    1 => Ok(Json(())),
+                    _ => Err(Error::UserEvalsDisabled),
+                }
+            }),
+        )
+        .route_layer(middleware::from_fn(observe_failures));
+
+    for (uri, status) in [
+        ("/?multi_pv=1", StatusCode::OK),
+        ("/?multi_pv=2", StatusCode::NOT_IMPLEMENTED),
+        ("/?multi_pv=6", StatusCode::BAD_REQUEST),
+        ("/?multi_pv=x", StatusCode::BAD_REQUEST),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(Request::get(uri).body(Body::empty()).expect("request"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), status, "{uri}");
+    }
+
+    // Handlers record their own successes.
+    let text = METRICS.render();
+    assert!(text.contains("cloudeval_requests_total{outcome=\"user_evals_disabled\"} 1\n"));
+    assert!(text.contains("cloudeval_requests_total{outcome=\"bad_request\"} 2\n"));
+    assert!(!text.contains("cloudeval_requests_total{outcome=\"hit\"}"));
+}
@@ -0,0 +1,271 @@
+use std::time::SystemTime;
+
+use lila_cloudeval::{
+    cdb_moves::{RelativeScore, ScoredMove, ScoredMoves},
+    database::{Analysis, Pv, WhiteScore},
+    error::Error,
+    eval::Eval,
+    protocol::{
+        parse_batch, play_moves, BatchResult, ClientMessage, EvalHit, LilaVariant, QueryAll,
+    },
+    score::Score,
+    user_eval::InvalidUserEval,
+};
+use serde_json::json;
+use shakmaty::{fen::Fen, uci::UciMove, variant::Variant, CastlingMode, Chess, EnPassantMode};
+
+fn uci_moves(moves: &str) -> Vec<UciMove> {
+    moves
+        .split(' ')
+        .map(|uci| uci.parse().expect("uci"))
+        .collect()
+}
+
+#[test]
+fn test_eval_hit() {
+    let fen: Fen = "rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 3"
+        .parse()
+        .expect("fen");
+
+    let analysis = Analysis {
+        pvs: vec![
+            Pv {
+                score: WhiteScore::Mate(-1),
+                moves: uci_moves("d8h4"),
+                truncated: false,
+            },
+            Pv {
+                score: WhiteScore::Cp(-248),
+                moves: uci_moves("h7h5 g4g5 d8g5 f1h3 g5h4 e1f1 b8c6 b1c3 g8e7 d2d3"),
+                truncated: true,
+            },
+        ],
+        ply_from_root: Some(5),
+    };
+
+    assert_eq!(
+        serde_json::to_value(EvalHit::new(
+            fen,
+            Some("".to_owned()),
+            Eval::Chessdb(analysis)
+        ))
+        .expect("json"),
+        json!({
+            "fen": "rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 3",
+            "knodes": 40000,
+            "depth": 20,
+            "pvs": [{
+                "moves": "d8h4",
+                "mate": -1,
+            }, {
+                "moves": "h7h5 g4g5 d8g5 f1h3 g5h4 e1f1 b8c6 b1c3 g8e7 d2d3",
+                "cp": -248,
+                "truncated": true,
+            }],
+            "source": "chessdb",
+            "path": "",
+        })
+    );
+}
+
+#[test]
+fn test_eval_get() {
+    let ClientMessage::EvalGet(eval_get) = serde_json::from_str(
+        r#"{
+            "t": "evalGet",
+            "d": {
+                "fen": "rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 3",
+                "path": "/?WG)8\\M(D",
+                "variant": "fromPosition",
+                "mpv": 2
+            }
+        }"#,
+    )
+    .expect("eval get") else {
+        panic!("expected eval get");
+    };
+
+    assert_eq!(
+        eval_get.fen.to_string(),
+        "rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 3"
+    );
+    assert_eq!(eval_get.path.as_deref(), Some("/?WG)8\\M(D"));
+    assert_eq!(usize::from(eval_get.mpv), 2);
+    assert_eq!(eval_get.variant, LilaVariant::FromPosition);
+
+    assert!(serde_json::from_str::<ClientMessage>(
+        r#"{"t": "evalGet", "d": {"fen": "8/8/8/8/8/8/8/8 w - - 0 1", "mpv": 6}}"#
+    )
+    .is_err());
+}
+
+#[test]
+fn test_eval_put() {
+    let ClientMessage::EvalPut(mut eval_put) = serde_json::from_str(
+        r#"{
+            "t": "evalPut",
+            "d": {
+                "fen": "r1bqkbnr/pppp1ppp/2n5/1B2p3/4P3/5N2/PPPP1PPP/RNBQK2R b KQkq - 3 3",
+                "knodes": 72980,
+                "depth": 24,
+                "pvs": [
+                    {"moves": "g8f6 e1g1 f8c5", "cp": -13, "truncated": true},
+                    {"moves": "a7a6 b5a4 g8f6", "mate": 12}
+                ]
+            }
+        }"#,
+    )
+    .expect("eval put") else {
+        panic!("expected eval put");
+    };
+
+    assert_eq!(eval_put.pvs[1].score, WhiteScore::Mate(12));
+    assert!(!eval_put.pvs[0].truncated);
+    eval_put.pvs.swap(0, 1);
+    assert!(matches!(
+        eval_put.into_user_eval(SystemTime::UNIX_EPOCH),
+        Err(Error::InvalidUserEval(InvalidUserEval::UnsortedPvs {
+            index: 1
+        }))
+    ));
+}
+
+#[test]
+fn test_lila_variant() {
+    for (name, variant) in [
+        ("standard", Variant::Chess),
+        ("chess960", Variant::Chess),
+        ("fromPosition", Variant::Chess),
+        ("antichess", Variant::Antichess),
+        ("atomic", Variant::Atomic),
+        ("crazyhouse", Variant::Crazyhouse),
+        ("horde", Variant::Horde),
+        ("kingOfTheHill", Variant::KingOfTheHill),
+        ("racingKings", Variant::RacingKings),
+        ("threeCheck", Variant::ThreeCheck),
+    ] {
+        let lila_variant: LilaVariant = serde_json::from_value(json!(name)).expect("lila variant");
+        assert_eq!(Variant::from(lila_variant), variant);
+    }
+
+    let fen: Fen = "rnbqkbnr/pppppppp/8/1PP2PP1/PPPPPPPP/PPPPPPPP/PPPPPPPP/PPPPPPPP w kq - 0 1"
+        .parse()
+        .expect("fen");
+    assert_eq!(
+        LilaVariant::Horde
+            .position(fen.clone())
+            .expect("horde")
+            .variant(),
+        Variant::Horde
+    );
+
+    // White has no king, which is only valid in horde.
+    assert!(matches!(
+        LilaVariant::Standard.position(fen),
+        Err(Error::PositionError(_))
+    ));
+}
+
+#[test]
+fn test_query_all() {
+    let fen: Fen = "r1bqkbnr/pppp1ppp/2n5/1B2p3/4P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4"
+        .parse()
+        .expect("fen");
+    let pos: Chess = fen
+        .clone()
+        .into_position(CastlingMode::Chess960)
+        .expect("legal");
+
+    let mut scored_moves = ScoredMoves::new();
+    scored_moves.set_ply_from_root(Some(7));
+    for (uci, cp) in [("e1h1", 30), ("d2d3", 25), ("b1c3", 30), ("b5c6", -40)] {
+        scored_moves.push(ScoredMove {
+            uci: uci.parse().expect("uci"),
+            score: RelativeScore(Score::Cp(cp)),
+        });
+    }
+
+    assert_eq!(
+        serde_json::to_value(QueryAll::new(fen, &pos, scored_moves.into_sorted())).expect("json"),
+        json!({
+            "fen": "r1bqkbnr/pppp1ppp/2n5/1B2p3/4P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+            "ply_from_root": 7,
+            "moves": [
+                {"uci": "e1h1", "san": "O-O", "cp": 30, "rank": 1, "good": true},
+                {"uci": "b1c3", "san": "Nc3", "cp": 30, "rank": 1, "good": true},
+                {"uci": "d2d3", "san": "d3", "cp": 25, "rank": 3, "good": true},
+                {"uci": "b5c6", "san": "Bxc6", "cp": -40, "rank": 4, "good": false},
+            ],
+        })
+    );
+}
+
+#[test]
+fn test_parse_batch() {
+    let fen = "rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 3";
+
+    let items = parse_batch(&format!(
+        r#"[{{"fen": "{fen}", "multi_pv": 2}}, {{"fen": "invalid"}}, {{"fen": "{fen}", "variant": "atomic"}}]"#
+    ))
+    .expect("json array");
+    assert_eq!(items.len(), 3);
+    let item = items[0].as_ref().expect("valid item");
+    assert_eq!(item.fen.to_string(), fen);
+    assert_eq!(usize::from(item.multi_pv), 2);
+    assert_eq!(item.variant, LilaVariant::Standard);
+    assert!(matches!(items[1], Err(Error::InvalidJson(_))));
+    assert_eq!(
+        items[2].as_ref().expect("valid item").variant,
+        LilaVariant::Atomic
+    );
+
+    let items = parse_batch(&format!(
+        "{{\"fen\": \"{fen}\"}}\n\n{{\"fen\": \"{fen}\", \"multi_pv\": 6}}\nnot json\n"
+    ))
+    .expect("ndjson");
+    assert_eq!(items.len(), 3);
+    assert_eq!(
+        usize::from(items[0].as_ref().expect("valid item").multi_pv),
+        1
+    );
+    assert!(items[1].is_err());
+    assert!(items[2].is_err());
+
+    assert!(parse_batch("[{").is_err());
+}
+
+#[test]
+fn test_batch_result() {
+    assert_eq!(
+        serde_json::to_value(BatchResult::from(Ok(None))).expect("json"),
+        json!(null)
+    );
+    assert_eq!(
+        serde_json::to_value(BatchResult::from(Err(Error::UserEvalsDisabled))).expect("json"),
+        json!({"error": "user evals are not enabled"})
+    );
+}
+
+#[test]
+fn test_play_moves() {
+    let start = LilaVariant::Standard
+        .position(Fen::default())
+        .expect("start position");
+
+    let (pos, history) =
+        play_moves(start.clone(), &uci_moves("g1f3 g8f6 f3g1 f6g8 e2e4")).expect("legal");
+    assert_eq!(
+        Fen::from_position(pos, EnPassantMode::Legal).to_string(),
+        "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 3"
+    );
+    // Positions before a pawn move can not repeat.
+    assert!(history.is_empty());
+
+    let (_, history) = play_moves(start.clone(), &uci_moves("g1f3 g8f6")).expect("legal");
+    assert_eq!(history.len(), 2);
+
+    assert!(matches!(
+        play_moves(start, &uci_moves("e2e4 e2e4")),
+        Err(Error::IllegalMove { .. })
+    ));
+}
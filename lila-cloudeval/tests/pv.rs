@@ -0,0 +1,296 @@
+use std::time::{Duration, Instant};
+
+use lila_cloudeval::{
+    cdb_moves::{CdbDecodeError, RelativeScore, ScoredMove, ScoredMoves},
+    database::{get_multi_pv, get_multi_pvs, Analysis, PvBudget, PvRequest, WhiteScore},
+    error::Error,
+    score::Score,
+    store::MemoryStore,
+};
+use shakmaty::{
+    uci::UciMove,
+    zobrist::{Zobrist64, ZobristHash},
+    Chess, EnPassantMode, Position,
+};
+
+fn play(line: &[&str]) -> Chess {
+    let mut pos = Chess::default();
+    for uci in line {
+        let m = uci
+            .parse::<UciMove>()
+            .expect("uci")
+            .to_move(&pos)
+            .expect("legal");
+        pos.play_unchecked(&m);
+    }
+    pos
+}
+
+fn insert(
+    store: &mut MemoryStore,
+    line: &[&str],
+    ply_from_root: Option<u32>,
+    moves: &[(&str, i16)],
+) {
+    let mut scored_moves = ScoredMoves::new();
+    scored_moves.set_ply_from_root(ply_from_root);
+    for (uci, cp) in moves {
+        scored_moves.push(ScoredMove {
+            uci: uci.parse().expect("uci"),
+            score: RelativeScore(Score::Cp(*cp)),
+        });
+    }
+    store
+        .insert_scored_moves(&play(line).into_setup(EnPassantMode::Legal), &scored_moves)
+        .expect("insert");
+}
+
+fn pvs(analysis: &Analysis) -> Vec<(WhiteScore, String)> {
+    analysis
+        .pvs
+        .iter()
+        .map(|pv| {
+            (
+                pv.score,
+                pv.moves
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_multi_pv() {
+    let mut store = MemoryStore::new();
+    insert(
+        &mut store,
+        &[],
+        Some(0),
+        &[("e2e4", 30), ("d2d4", 25), ("g1f3", 10)],
+    );
+    insert(&mut store, &["e2e4"], None, &[("e7e5", -30)]);
+    insert(&mut store, &["e2e4", "e7e5"], None, &[("g1f3", 30)]);
+    insert(&mut store, &["d2d4"], None, &[("d7d5", -25)]);
+
+    let analysis = get_multi_pv(&store, None, &PvRequest::new(&play(&[]), 2))
+        .expect("get multi pv")
+        .expect("found");
+    assert_eq!(analysis.ply_from_root, Some(0));
+    assert_eq!(
+        pvs(&analysis),
+        [
+            (WhiteScore::Cp(30), "e2e4 e7e5 g1f3".to_owned()),
+            (WhiteScore::Cp(25), "d2d4 d7d5".to_owned()),
+        ]
+    );
+
+    // Scores are reported from the point of view of white.
+    let analysis = get_multi_pv(&store, None, &PvRequest::new(&play(&["e2e4"]), 1))
+        .expect("get multi pv")
+        .expect("found");
+    assert_eq!(analysis.ply_from_root, None);
+    assert_eq!(
+        pvs(&analysis),
+        [(WhiteScore::Cp(30), "e7e5 g1f3".to_owned())]
+    );
+
+    // Not enough moves to satisfy the request.
+    assert!(get_multi_pv(&store, None, &PvRequest::new(&play(&[]), 4))
+        .expect("get multi pv")
+        .is_none());
+
+    // Position not found.
+    assert!(
+        get_multi_pv(&store, None, &PvRequest::new(&play(&["g1f3"]), 1))
+            .expect("get multi pv")
+            .is_none()
+    );
+}
+
+#[test]
+fn test_tiebreak() {
+    let mut store = MemoryStore::new();
+    insert(&mut store, &[], None, &[("e2e4", 20), ("d2d4", 20)]);
+    insert(
+        &mut store,
+        &["e2e4"],
+        None,
+        &[("e7e5", -20), ("c7c5", -20), ("e7e6", -20)],
+    );
+    insert(&mut store, &["d2d4"], None, &[("d7d5", -20)]);
+
+    // Prefer the move that leaves the opponent with fewer good replies.
+    let analysis = get_multi_pv(&store, None, &PvRequest::new(&play(&[]), 2))
+        .expect("get multi pv")
+        .expect("found");
+    assert_eq!(
+        pvs(&analysis),
+        [
+            (WhiteScore::Cp(20), "d2d4 d7d5".to_owned()),
+            (WhiteScore::Cp(20), "e2e4 e7e5".to_owned()),
+        ]
+    );
+}
+
+#[test]
+fn test_repetition() {
+    let mut store = MemoryStore::new();
+    insert(&mut store, &[], None, &[("g1f3", 0)]);
+    insert(&mut store, &["g1f3"], None, &[("g8f6", 0)]);
+    insert(&mut store, &["g1f3", "g8f6"], None, &[("f3g1", 0)]);
+    insert(&mut store, &["g1f3", "g8f6", "f3g1"], None, &[("f6g8", 0)]);
+
+    let analysis = get_multi_pv(&store, None, &PvRequest::new(&play(&[]), 1))
+        .expect("get multi pv")
+        .expect("found");
+    assert_eq!(
+        pvs(&analysis),
+        [(WhiteScore::Cp(0), "g1f3 g8f6 f3g1 f6g8".to_owned())]
+    );
+
+    // Positions from earlier in the game count as repetitions, too.
+    let history: [Zobrist64; 1] = [play(&["g1f3"]).zobrist_hash(EnPassantMode::Legal)];
+    let analysis = get_multi_pv(
+        &store,
+        None,
+        &PvRequest {
+            history: &history,
+            ..PvRequest::new(&play(&[]), 1)
+        },
+    )
+    .expect("get multi pv")
+    .expect("found");
+    assert_eq!(pvs(&analysis), [(WhiteScore::Cp(0), "g1f3".to_owned())]);
+}
+
+#[test]
+fn test_illegal_move() {
+    // Corrupt rows are reported, both at the root and further down the pv.
+    let mut store = MemoryStore::new();
+    insert(&mut store, &[], None, &[("e2e5", 0)]);
+    assert!(matches!(
+        get_multi_pv(&store, None, &PvRequest::new(&play(&[]), 1)),
+        Err(Error::CdbDecodeError(CdbDecodeError::IllegalMove { .. }))
+    ));
+
+    let mut store = MemoryStore::new();
+    insert(&mut store, &[], None, &[("e2e4", 0)]);
+    insert(&mut store, &["e2e4"], None, &[("e7e5", 0)]);
+    insert(&mut store, &["e2e4", "e7e5"], None, &[("e1e3", 0)]);
+    assert!(matches!(
+        get_multi_pv(&store, None, &PvRequest::new(&play(&[]), 1)),
+        Err(Error::CdbDecodeError(CdbDecodeError::IllegalMove { .. }))
+    ));
+}
+
+#[test]
+fn test_multi_pvs() {
+    let mut store = MemoryStore::new();
+    insert(&mut store, &[], None, &[("e2e4", 30), ("d2d4", 25)]);
+    insert(&mut store, &["e2e4"], None, &[("e7e5", -30)]);
+    insert(&mut store, &["e2e4", "e7e5"], None, &[("g1f3", 30)]);
+
+    let start = play(&[]);
+    let e4 = play(&["e2e4"]);
+    let nf3 = play(&["g1f3"]);
+    let results = get_multi_pvs(
+        &store,
+        None,
+        &[
+            PvRequest::new(&start, 2),
+            PvRequest::new(&nf3, 1),
+            PvRequest::new(&e4, 1),
+            PvRequest::new(&start, 3),
+        ],
+    );
+    assert_eq!(results.len(), 4);
+
+    for (result, (pos, multi_pv)) in
+        results
+            .into_iter()
+            .zip([(&start, 2), (&nf3, 1), (&e4, 1), (&start, 3)])
+    {
+        assert_eq!(
+            result.expect("get multi pvs").as_ref().map(pvs),
+            get_multi_pv(&store, None, &PvRequest::new(pos, multi_pv))
+                .expect("get multi pv")
+                .as_ref()
+                .map(pvs)
+        );
+    }
+}
+
+#[test]
+fn test_budget() {
+    let mut store = MemoryStore::new();
+    insert(&mut store, &[], None, &[("g1f3", 0)]);
+    insert(&mut store, &["g1f3"], None, &[("g8f6", 0)]);
+    insert(&mut store, &["g1f3", "g8f6"], None, &[("f3g1", 0)]);
+    insert(&mut store, &["g1f3", "g8f6", "f3g1"], None, &[("f6g8", 0)]);
+
+    let start = play(&[]);
+    let line = |budget: PvBudget| {
+        let analysis = get_multi_pv(
+            &store,
+            None,
+            &PvRequest {
+                budget,
+                ..PvRequest::new(&start, 1)
+            },
+        )
+        .expect("get multi pv")
+        .expect("found");
+        let pv = &analysis.pvs[0];
+        (pvs(&analysis)[0].1.clone(), pv.truncated)
+    };
+
+    assert_eq!(
+        line(PvBudget::default()),
+        ("g1f3 g8f6 f3g1 f6g8".to_owned(), false)
+    );
+    assert_eq!(
+        line(PvBudget {
+            max_plies: 4,
+            ..PvBudget::default()
+        }),
+        ("g1f3 g8f6 f3g1 f6g8".to_owned(), false)
+    );
+    assert_eq!(
+        line(PvBudget {
+            max_plies: 2,
+            ..PvBudget::default()
+        }),
+        ("g1f3 g8f6".to_owned(), true)
+    );
+    assert_eq!(
+        line(PvBudget {
+            max_lookups: 1,
+            ..PvBudget::default()
+        }),
+        ("g1f3 g8f6".to_owned(), true)
+    );
+    assert_eq!(
+        line(PvBudget {
+            deadline: Some(Instant::now()),
+            ..PvBudget::default()
+        }),
+        ("g1f3".to_owned(), true)
+    );
+    assert_eq!(
+        line(PvBudget {
+            time_limit: Some(Duration::ZERO),
+            ..PvBudget::default()
+        }),
+        ("g1f3".to_owned(), true)
+    );
+    assert_eq!(
+        line(PvBudget {
+            time_limit: Some(Duration::from_secs(60)),
+            ..PvBudget::default()
+        }),
+        ("g1f3 g8f6 f3g1 f6g8".to_owned(), false)
+    );
+}
@@ -0,0 +1,71 @@
+use lila_cloudeval::score::Score;
+
+#[test]
+fn test_score_from_cdb() {
+    assert_eq!(Score::from_cdb(0), Score::Cp(0));
+    assert_eq!(Score::from_cdb(-123), Score::Cp(-123));
+    assert_eq!(Score::from_cdb(29999), Score::MateIn(1));
+    assert_eq!(Score::from_cdb(-29998), Score::MatedIn(2));
+    assert_eq!(Score::from_cdb(24990), Score::TbWin(10));
+    assert_eq!(Score::from_cdb(-24990), Score::TbLoss(10));
+    assert_eq!(Score::from_cdb(19950), Score::CursedWin(50));
+    assert_eq!(Score::from_cdb(-19950), Score::BlessedLoss(50));
+}
+
+#[test]
+fn test_score_roundtrip() {
+    for value in -30000..=30000 {
+        let score = Score::from_cdb(value);
+        if !matches!(score, Score::Cp(cp) if cp.abs() > 19000) {
+            assert_eq!(score.to_cdb(), value, "{score:?}");
+        }
+        assert_eq!(-(-score), score);
+        assert_eq!(Score::from_cdb(-value), -score);
+    }
+}
+
+#[test]
+fn test_score_exact_roundtrip() {
+    // All values accepted when reading chessdb.cn records.
+    for value in -30000..=30000 {
+        assert_eq!(Score::from_cdb(value).to_cdb_exact(), value);
+    }
+
+    // Centipawns that would decode to a different score are saturated.
+    assert_eq!(Score::Cp(19500).to_cdb_exact(), 19000);
+    assert_eq!(Score::Cp(-24500).to_cdb_exact(), -19000);
+    assert_eq!(Score::Cp(i16::MAX).to_cdb_exact(), 19000);
+}
+
+#[test]
+fn test_score_order() {
+    let ordered = [
+        Score::MatedIn(0),
+        Score::MatedIn(2),
+        Score::TbLoss(1),
+        Score::TbLoss(100),
+        Score::BlessedLoss(3),
+        Score::Cp(-300),
+        Score::Cp(0),
+        Score::Cp(17),
+        Score::CursedWin(3),
+        Score::TbWin(100),
+        Score::TbWin(1),
+        Score::MateIn(3),
+        Score::MateIn(1),
+    ];
+
+    for pair in ordered.windows(2) {
+        assert!(pair[0] < pair[1], "{:?} < {:?}", pair[0], pair[1]);
+        assert!(-pair[1] < -pair[0], "-{:?} < -{:?}", pair[1], pair[0]);
+    }
+}
+
+#[test]
+fn test_score_mate_moves() {
+    assert_eq!(Score::MateIn(1).mate_moves(), Some(1));
+    assert_eq!(Score::MateIn(3).mate_moves(), Some(2));
+    assert_eq!(Score::MatedIn(2).mate_moves(), Some(-1));
+    assert_eq!(Score::MatedIn(4).mate_moves(), Some(-2));
+    assert_eq!(Score::Cp(100).mate_moves(), None);
+}
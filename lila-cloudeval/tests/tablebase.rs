@@ -0,0 +1,120 @@
+use lila_cloudeval::{
+    cdb_moves::{RelativeScore, ScoredMove, ScoredMoves},
+    database::{get_multi_pv, PvBudget, PvRequest, WhiteScore},
+    score::Score,
+    store::MemoryStore,
+    tablebase::Tablebases,
+};
+use shakmaty::{fen::Fen, uci::UciMove, CastlingMode, Chess, EnPassantMode, Position};
+
+// Expects (at least) the 3-4-5 piece Syzygy tables in tests/syzygy.
+fn tablebases() -> Tablebases {
+    Tablebases::open(&["tests/syzygy"]).expect("syzygy tables")
+}
+
+fn pos(fen: &str) -> Chess {
+    fen.parse::<Fen>()
+        .expect("fen")
+        .into_position(CastlingMode::Chess960)
+        .expect("legal")
+}
+
+#[test]
+fn test_tablebase_multi_pv() {
+    let tablebases = tablebases();
+
+    let root = pos("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1");
+    assert!(tablebases.covers(&root));
+
+    let analysis = tablebases
+        .get_multi_pv(&root, 2, &PvBudget::default())
+        .expect("probe")
+        .expect("covered");
+    assert_eq!(analysis.pvs.len(), 2);
+
+    // Qg8# is the only mate in one.
+    assert_eq!(analysis.pvs[0].score, WhiteScore::Mate(1));
+    assert_eq!(
+        analysis.pvs[0].moves,
+        ["g1g8".parse::<UciMove>().expect("uci")]
+    );
+
+    // Everything else is a tablebase win, reported in centipawns.
+    let WhiteScore::Cp(cp) = analysis.pvs[1].score else {
+        panic!("expected tablebase win");
+    };
+    assert!(matches!(Score::from_cdb(cp), Score::TbWin(_)));
+
+    // Pvs are cut short by the budget.
+    let analysis = tablebases
+        .get_multi_pv(
+            &root,
+            2,
+            &PvBudget {
+                max_lookups: 0,
+                ..PvBudget::default()
+            },
+        )
+        .expect("probe")
+        .expect("covered");
+    assert_eq!(analysis.pvs[0].moves.len(), 1);
+    assert!(!analysis.pvs[0].truncated, "mate ends the pv");
+    assert_eq!(analysis.pvs[1].moves.len(), 1);
+    assert!(analysis.pvs[1].truncated);
+
+    // Nothing to analyze after checkmate or stalemate.
+    for fen in [
+        "k7/1Q6/1K6/8/8/8/8/8 b - - 0 1",
+        "k7/8/1Q6/8/8/8/8/7K b - - 0 1",
+    ] {
+        assert!(tablebases
+            .get_multi_pv(&pos(fen), 1, &PvBudget::default())
+            .expect("probe")
+            .is_none());
+    }
+
+    // Castling rights are never covered.
+    assert!(!tablebases.covers(&pos("4k3/8/8/8/8/8/8/4K2R w K - 0 1")));
+}
+
+#[test]
+fn test_pv_ends_in_tablebase_territory() {
+    let root = pos("7k/p7/8/8/8/8/r6P/R3K3 w - - 0 1");
+
+    let mut store = MemoryStore::new();
+    let mut insert = |pos: &Chess, uci: &str, cp: i16| {
+        let mut scored_moves = ScoredMoves::new();
+        scored_moves.push(ScoredMove {
+            uci: uci.parse().expect("uci"),
+            score: RelativeScore(Score::Cp(cp)),
+        });
+        store
+            .insert_scored_moves(&pos.clone().into_setup(EnPassantMode::Legal), &scored_moves)
+            .expect("insert");
+    };
+    let mut child = root.clone();
+    insert(&root, "a1a2", 900);
+    child.play_unchecked(
+        &"a1a2"
+            .parse::<UciMove>()
+            .expect("uci")
+            .to_move(&child)
+            .expect("legal"),
+    );
+    insert(&child, "h8g7", -900);
+
+    let line = |tablebases: Option<&Tablebases>| {
+        get_multi_pv(&store, tablebases, &PvRequest::new(&root, 1))
+            .expect("get multi pv")
+            .expect("found")
+            .pvs[0]
+            .moves
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    assert_eq!(line(None), "a1a2 h8g7");
+    assert_eq!(line(Some(&tablebases())), "a1a2");
+}
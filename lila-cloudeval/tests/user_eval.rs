@@ -0,0 +1,212 @@
+use std::time::{Duration, SystemTime};
+
+use lila_cloudeval::{
+    database::{Pv, WhiteScore},
+    user_eval::{
+        user_eval_key, InvalidUserEval, UserEval, UserEvalDecodeError, UserEvalEncodeError,
+        MAX_PV_PLIES,
+    },
+};
+use shakmaty::{fen::Fen, uci::UciMove, variant::Variant, CastlingMode, Chess};
+
+fn pv(score: WhiteScore, moves: &str) -> Pv {
+    Pv {
+        score,
+        moves: moves
+            .split(' ')
+            .map(|uci| uci.parse::<UciMove>().expect("uci"))
+            .collect(),
+        truncated: false,
+    }
+}
+
+fn user_eval() -> UserEval {
+    UserEval {
+        pvs: vec![
+            pv(WhiteScore::Mate(-1), "d8h4"),
+            pv(
+                WhiteScore::Cp(-248),
+                "h7h5 g4g5 d8g5 f1h3 g5h4 e1f1 b8c6 b1c3 g8e7 d2d3",
+            ),
+        ],
+        depth: 30,
+        knodes: 133793,
+        submitted_at: SystemTime::UNIX_EPOCH + Duration::from_secs(1_726_000_000),
+    }
+}
+
+#[test]
+fn test_roundtrip() {
+    for user_eval in [
+        user_eval(),
+        UserEval {
+            pvs: vec![pv(WhiteScore::Cp(0), "P@e4 e8g8")],
+            depth: 0,
+            knodes: u64::MAX,
+            submitted_at: SystemTime::UNIX_EPOCH,
+        },
+    ] {
+        let mut buf = Vec::new();
+        user_eval.write(&mut buf).expect("write");
+        assert_eq!(UserEval::try_read(&mut &buf[..]), Ok(user_eval));
+    }
+}
+
+#[test]
+fn test_write_errors() {
+    let mut buf = Vec::new();
+
+    let many = UserEval {
+        pvs: vec![pv(WhiteScore::Cp(0), "e2e4"); 256],
+        ..user_eval()
+    };
+    assert_eq!(
+        many.write(&mut buf),
+        Err(UserEvalEncodeError::TooManyPvs { n: 256 })
+    );
+
+    let long = UserEval {
+        pvs: vec![pv(WhiteScore::Cp(0), &vec!["e2e4"; 15_000].join(" "))],
+        ..user_eval()
+    };
+    assert_eq!(
+        long.write(&mut buf),
+        Err(UserEvalEncodeError::PvTooLong { index: 0 })
+    );
+
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn test_try_read_errors() {
+    let mut buf = Vec::new();
+    user_eval().write(&mut buf).expect("write");
+
+    assert_eq!(
+        UserEval::try_read(&mut &buf[..buf.len() - 1]),
+        Err(UserEvalDecodeError::Truncated)
+    );
+    assert_eq!(
+        UserEval::try_read(&mut &[&buf[..], &[0]].concat()[..]),
+        Err(UserEvalDecodeError::TrailingBytes)
+    );
+    assert_eq!(
+        UserEval::try_read(&mut &[42][..]),
+        Err(UserEvalDecodeError::UnknownVersion { version: 42 })
+    );
+
+    let mut invalid_score = buf.clone();
+    invalid_score[1 + 4 + 8 + 8 + 1] = 2;
+    assert_eq!(
+        UserEval::try_read(&mut &invalid_score[..]),
+        Err(UserEvalDecodeError::InvalidScore { tag: 2 })
+    );
+
+    let mut invalid_move = buf.clone();
+    invalid_move[1 + 4 + 8 + 8 + 1 + 1 + 4 + 2] = b'x';
+    assert_eq!(
+        UserEval::try_read(&mut &invalid_move[..]),
+        Err(UserEvalDecodeError::InvalidMove)
+    );
+}
+
+#[test]
+fn test_user_eval_key() {
+    let fen: Fen = "rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 3"
+        .parse()
+        .expect("fen");
+
+    // Move counters are not part of the key.
+    let other_fen: Fen = "rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 5 42"
+        .parse()
+        .expect("fen");
+    assert_eq!(
+        user_eval_key(fen.clone().into_setup(), Variant::Chess),
+        user_eval_key(other_fen.into_setup(), Variant::Chess)
+    );
+
+    // The variant is.
+    assert_ne!(
+        user_eval_key(fen.clone().into_setup(), Variant::Chess),
+        user_eval_key(fen.into_setup(), Variant::KingOfTheHill)
+    );
+}
+
+#[test]
+fn test_validate() {
+    let pos: Chess = "rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 3"
+        .parse::<Fen>()
+        .expect("fen")
+        .into_position(CastlingMode::Chess960)
+        .expect("legal");
+
+    let mut valid = user_eval();
+    assert_eq!(valid.validate(&pos), Ok(()));
+    assert_eq!(valid, user_eval());
+
+    let mut shallow = UserEval {
+        depth: 19,
+        ..user_eval()
+    };
+    assert_eq!(
+        shallow.validate(&pos),
+        Err(InvalidUserEval::DepthTooLow { depth: 19 })
+    );
+
+    let mut illegal = UserEval {
+        pvs: vec![pv(WhiteScore::Mate(-1), "d8h4 e1e2")],
+        ..user_eval()
+    };
+    assert_eq!(
+        illegal.validate(&pos),
+        Err(InvalidUserEval::IllegalMove {
+            index: 0,
+            uci: "e1e2".parse().expect("uci")
+        })
+    );
+
+    let mut unsorted = user_eval();
+    unsorted.pvs.reverse();
+    assert_eq!(
+        unsorted.validate(&pos),
+        Err(InvalidUserEval::UnsortedPvs { index: 1 })
+    );
+
+    let mut duplicate = UserEval {
+        pvs: vec![
+            pv(WhiteScore::Mate(-1), "d8h4"),
+            pv(WhiteScore::Mate(-1), "d8h4"),
+        ],
+        ..user_eval()
+    };
+    assert_eq!(
+        duplicate.validate(&pos),
+        Err(InvalidUserEval::DuplicateFirstMove { index: 1 })
+    );
+
+    let shuffle = ["g1h3 g8f6 h3g1 f6g8"; MAX_PV_PLIES / 4 + 1].join(" ");
+    let mut long = UserEval {
+        pvs: vec![pv(WhiteScore::Cp(30), &format!("b8c6 {shuffle}"))],
+        ..user_eval()
+    };
+    assert_eq!(
+        long.validate(&pos),
+        Err(InvalidUserEval::PvTooLong {
+            index: 0,
+            plies: MAX_PV_PLIES + 5
+        })
+    );
+
+    // Castling is rewritten as king moves to rook.
+    let pos: Chess = "r1bqkbnr/pppp1ppp/2n5/1B2p3/4P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4"
+        .parse::<Fen>()
+        .expect("fen")
+        .into_position(CastlingMode::Chess960)
+        .expect("legal");
+    let mut castling = UserEval {
+        pvs: vec![pv(WhiteScore::Cp(30), "e1g1 g8f6")],
+        ..user_eval()
+    };
+    assert_eq!(castling.validate(&pos), Ok(()));
+    assert_eq!(castling.pvs, [pv(WhiteScore::Cp(30), "e1h1 g8f6")]);
+}
@@ -1,6 +1,9 @@
 use std::ptr::NonNull;
 
-use terarkdb_sys::{rocksdb_cache_create_lru, rocksdb_cache_destroy, rocksdb_cache_t};
+use terarkdb_sys::{
+    rocksdb_cache_create_lru, rocksdb_cache_destroy, rocksdb_cache_get_pinned_usage,
+    rocksdb_cache_get_usage, rocksdb_cache_set_capacity, rocksdb_cache_t,
+};
 
 #[derive(Debug)]
 pub struct Cache {
@@ -14,6 +17,24 @@ impl Cache {
         }
     }
 
+    /// Memory used by entries in the cache, in bytes.
+    pub fn usage(&self) -> usize {
+        unsafe { rocksdb_cache_get_usage(self.as_implied_const_ptr()) }
+    }
+
+    /// Memory used by entries that are currently in use and cannot be
+    /// evicted, in bytes.
+    pub fn pinned_usage(&self) -> usize {
+        unsafe { rocksdb_cache_get_pinned_usage(self.as_implied_const_ptr()) }
+    }
+
+    /// Changes the capacity, evicting entries if necessary.
+    pub fn set_capacity(&self, capacity_bytes: usize) {
+        unsafe {
+            rocksdb_cache_set_capacity(self.as_implied_const_ptr(), capacity_bytes);
+        }
+    }
+
     pub(crate) fn as_implied_const_ptr(&self) -> *mut rocksdb_cache_t {
         self.inner.as_ptr()
     }
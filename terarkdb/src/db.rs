@@ -1,17 +1,27 @@
 use std::{
-    ffi::{c_char, c_uchar, CString},
+    ffi::{c_char, c_int, c_uchar, CString},
+    ops::Range,
     path::Path,
     ptr::NonNull,
 };
 
 use terarkdb_sys::{
-    rocksdb_close, rocksdb_get, rocksdb_get_pinned, rocksdb_multi_get, rocksdb_open,
-    rocksdb_open_for_read_only, rocksdb_t,
+    rocksdb_approximate_sizes, rocksdb_close, rocksdb_delete, rocksdb_get, rocksdb_get_pinned,
+    rocksdb_multi_get, rocksdb_open, rocksdb_open_for_read_only, rocksdb_property_int,
+    rocksdb_property_value, rocksdb_put, rocksdb_t, rocksdb_write,
 };
 
 use crate::{
-    error::Error, multi_get::MultiGet, options::Options, pinnable_slice::PinnableSlice,
-    read_options::ReadOptions, util::Malloced, MallocedBytes,
+    error::Error,
+    multi_get::MultiGet,
+    options::Options,
+    pinnable_slice::PinnableSlice,
+    read_options::ReadOptions,
+    snapshot::Snapshot,
+    util::{malloced_string, Malloced},
+    write_batch::WriteBatch,
+    write_options::WriteOptions,
+    MallocedBytes,
 };
 
 fn cpath(path: &Path) -> CString {
@@ -19,6 +29,10 @@ fn cpath(path: &Path) -> CString {
     CString::new(path.as_os_str().as_bytes()).expect("no NUL in unix path")
 }
 
+fn cproperty(name: &str) -> CString {
+    CString::new(name).expect("no NUL in property name")
+}
+
 #[derive(Default)]
 pub enum LogFile {
     #[default]
@@ -164,6 +178,146 @@ impl Db {
         multi_get
     }
 
+    pub fn put<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, key: K, value: V) -> Result<(), Error> {
+        self.put_opt(key, value, &WriteOptions::default())
+    }
+
+    pub fn put_opt<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &self,
+        key: K,
+        value: V,
+        write_options: &WriteOptions,
+    ) -> Result<(), Error> {
+        let key = key.as_ref();
+        let value = value.as_ref();
+        let mut error = None;
+        unsafe {
+            rocksdb_put(
+                self.as_mut_ptr(),
+                write_options.as_ptr(),
+                key.as_ptr().cast::<c_char>(),
+                key.len(),
+                value.as_ptr().cast::<c_char>(),
+                value.len(),
+                Error::out_ptr(&mut error),
+            );
+        }
+
+        error.map_or(Ok(()), Err)
+    }
+
+    pub fn delete<K: AsRef<[u8]>>(&self, key: K) -> Result<(), Error> {
+        self.delete_opt(key, &WriteOptions::default())
+    }
+
+    pub fn delete_opt<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+        write_options: &WriteOptions,
+    ) -> Result<(), Error> {
+        let key = key.as_ref();
+        let mut error = None;
+        unsafe {
+            rocksdb_delete(
+                self.as_mut_ptr(),
+                write_options.as_ptr(),
+                key.as_ptr().cast::<c_char>(),
+                key.len(),
+                Error::out_ptr(&mut error),
+            );
+        }
+
+        error.map_or(Ok(()), Err)
+    }
+
+    /// Atomically applies all operations of the batch.
+    pub fn write(&self, batch: &WriteBatch) -> Result<(), Error> {
+        self.write_opt(batch, &WriteOptions::default())
+    }
+
+    pub fn write_opt(&self, batch: &WriteBatch, write_options: &WriteOptions) -> Result<(), Error> {
+        let mut error = None;
+        unsafe {
+            rocksdb_write(
+                self.as_mut_ptr(),
+                write_options.as_ptr(),
+                batch.as_implied_const_ptr(),
+                Error::out_ptr(&mut error),
+            );
+        }
+
+        error.map_or(Ok(()), Err)
+    }
+
+    /// Gets a property like `rocksdb.stats`, or `None` if the property is
+    /// unknown.
+    pub fn property_value(&self, name: &str) -> Option<String> {
+        let name = cproperty(name);
+        unsafe { malloced_string(rocksdb_property_value(self.as_mut_ptr(), name.as_ptr())) }
+    }
+
+    /// Gets an integer property like `rocksdb.estimate-num-keys`, or `None`
+    /// if the property is unknown or not an integer.
+    pub fn property_int(&self, name: &str) -> Option<u64> {
+        let name = cproperty(name);
+        let mut value = 0;
+        let status = unsafe { rocksdb_property_int(self.as_mut_ptr(), name.as_ptr(), &mut value) };
+        (status == 0).then_some(value)
+    }
+
+    /// Estimated number of keys, including keys that are not yet compacted
+    /// away.
+    pub fn estimate_num_keys(&self) -> Option<u64> {
+        self.property_int("rocksdb.estimate-num-keys")
+    }
+
+    /// Memory used by entries in the block cache, in bytes.
+    pub fn block_cache_usage(&self) -> Option<u64> {
+        self.property_int("rocksdb.block-cache-usage")
+    }
+
+    /// Memory used by table readers, excluding the block cache, in bytes.
+    pub fn estimate_table_readers_mem(&self) -> Option<u64> {
+        self.property_int("rocksdb.estimate-table-readers-mem")
+    }
+
+    /// Approximates the file system space used by each key range, in bytes.
+    pub fn approximate_sizes<K: AsRef<[u8]>>(&self, ranges: &[Range<K>]) -> Vec<u64> {
+        let (start_ptrs, start_lens): (Vec<*const c_char>, Vec<usize>) = ranges
+            .iter()
+            .map(|range| {
+                let start = range.start.as_ref();
+                (start.as_ptr().cast::<c_char>(), start.len())
+            })
+            .unzip();
+        let (limit_ptrs, limit_lens): (Vec<*const c_char>, Vec<usize>) = ranges
+            .iter()
+            .map(|range| {
+                let limit = range.end.as_ref();
+                (limit.as_ptr().cast::<c_char>(), limit.len())
+            })
+            .unzip();
+
+        let mut sizes = vec![0; ranges.len()];
+        unsafe {
+            rocksdb_approximate_sizes(
+                self.as_mut_ptr(),
+                c_int::try_from(ranges.len()).unwrap(),
+                start_ptrs.as_ptr(),
+                start_lens.as_ptr(),
+                limit_ptrs.as_ptr(),
+                limit_lens.as_ptr(),
+                sizes.as_mut_ptr(),
+            );
+        }
+        sizes
+    }
+
+    /// Creates a snapshot of the current state, released on drop.
+    pub fn snapshot(&self) -> Snapshot<'_> {
+        Snapshot::new(self)
+    }
+
     pub(crate) fn as_mut_ptr(&self) -> *mut rocksdb_t {
         self.inner.as_ptr()
     }
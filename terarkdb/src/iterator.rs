@@ -1,9 +1,12 @@
-use std::{ffi::c_char, marker::PhantomData, ptr::NonNull, slice};
+use std::{
+    borrow::BorrowMut, ffi::c_char, iter::FusedIterator, marker::PhantomData, ptr::NonNull, slice,
+};
 
 use terarkdb_sys::{
     rocksdb_create_iterator, rocksdb_iter_destroy, rocksdb_iter_get_error, rocksdb_iter_key,
-    rocksdb_iter_next, rocksdb_iter_prev, rocksdb_iter_seek_to_first, rocksdb_iter_seek_to_last,
-    rocksdb_iter_valid, rocksdb_iter_value, rocksdb_iterator_t,
+    rocksdb_iter_next, rocksdb_iter_prev, rocksdb_iter_seek, rocksdb_iter_seek_for_prev,
+    rocksdb_iter_seek_to_first, rocksdb_iter_seek_to_last, rocksdb_iter_valid, rocksdb_iter_value,
+    rocksdb_iterator_t,
 };
 
 use crate::{db::Db, error::Error, read_options::ReadOptions};
@@ -12,11 +15,11 @@ use crate::{db::Db, error::Error, read_options::ReadOptions};
 pub struct Iterator<'db, 'options> {
     inner: NonNull<rocksdb_iterator_t>,
     db: PhantomData<&'db Db>,
-    options: PhantomData<&'options ReadOptions>, // for bounds
+    options: PhantomData<&'options ReadOptions<'options>>, // for bounds and snapshot
 }
 
 impl<'db, 'options> Iterator<'db, 'options> {
-    pub fn new(db: &'db Db, options: &'options ReadOptions) -> Iterator<'db, 'options> {
+    pub fn new(db: &'db Db, options: &'options ReadOptions<'_>) -> Iterator<'db, 'options> {
         Iterator {
             inner: NonNull::new(unsafe {
                 rocksdb_create_iterator(db.as_mut_ptr(), options.as_ptr())
@@ -51,6 +54,22 @@ impl<'db, 'options> Iterator<'db, 'options> {
         };
     }
 
+    /// Positions at the first key at or after `key`.
+    pub fn seek<K: AsRef<[u8]>>(&mut self, key: K) {
+        let key = key.as_ref();
+        unsafe {
+            rocksdb_iter_seek(self.as_mut_ptr(), key.as_ptr().cast::<c_char>(), key.len());
+        }
+    }
+
+    /// Positions at the last key at or before `key`.
+    pub fn seek_for_prev<K: AsRef<[u8]>>(&mut self, key: K) {
+        let key = key.as_ref();
+        unsafe {
+            rocksdb_iter_seek_for_prev(self.as_mut_ptr(), key.as_ptr().cast::<c_char>(), key.len());
+        }
+    }
+
     pub unsafe fn next_unchecked(&mut self) {
         unsafe {
             rocksdb_iter_next(self.as_mut_ptr());
@@ -117,6 +136,16 @@ impl<'db, 'options> Iterator<'db, 'options> {
         }
     }
 
+    /// Scans forward from the current position, borrowing the iterator.
+    pub fn entries(&mut self) -> Entries<&mut Iterator<'db, 'options>> {
+        Entries::new(self)
+    }
+
+    /// Scans forward from the current position.
+    pub fn into_entries(self) -> Entries<Iterator<'db, 'options>> {
+        Entries::new(self)
+    }
+
     pub(crate) fn as_ptr(&self) -> *const rocksdb_iterator_t {
         self.inner.as_ptr()
     }
@@ -134,3 +163,48 @@ impl Drop for Iterator<'_, '_> {
 
 unsafe impl Send for Iterator<'_, '_> {}
 unsafe impl Sync for Iterator<'_, '_> {}
+
+/// Adapter that scans an [`Iterator`] forward as a [`std::iter::Iterator`].
+///
+/// Keys and values are copied, because the underlying slices are only valid
+/// until the next move. Errors are checked with [`Iterator::status()`] once
+/// the end is reached.
+#[derive(Debug)]
+pub struct Entries<I> {
+    inner: I,
+    done: bool,
+}
+
+impl<I> Entries<I> {
+    fn new(inner: I) -> Entries<I> {
+        Entries { inner, done: false }
+    }
+
+    pub fn into_inner(self) -> I {
+        self.inner
+    }
+}
+
+impl<'db, 'options, I: BorrowMut<Iterator<'db, 'options>>> std::iter::Iterator for Entries<I> {
+    type Item = Result<(Box<[u8]>, Box<[u8]>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let inner = self.inner.borrow_mut();
+        if let Some((key, value)) = inner.item() {
+            let entry = (Box::from(key), Box::from(value));
+            unsafe {
+                inner.next_unchecked();
+            }
+            Some(Ok(entry))
+        } else {
+            self.done = true;
+            inner.status().err().map(Err)
+        }
+    }
+}
+
+impl<'db, 'options, I: BorrowMut<Iterator<'db, 'options>>> FusedIterator for Entries<I> {}
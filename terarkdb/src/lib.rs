@@ -9,14 +9,20 @@ mod multi_get;
 mod options;
 mod pinnable_slice;
 mod read_options;
+mod snapshot;
 mod util;
+mod write_batch;
+mod write_options;
 
 pub use block_based_table_options::BlockBasedTableOptions;
 pub use cache::Cache;
 pub use db::{Db, LogFile};
 pub use error::Error;
-pub use iterator::Iterator;
+pub use iterator::{Entries, Iterator};
 pub use multi_get::MultiGet;
 pub use options::Options;
 pub use read_options::ReadOptions;
+pub use snapshot::Snapshot;
 pub use util::MallocedBytes;
+pub use write_batch::{WriteBatch, WriteBatchRecord};
+pub use write_options::WriteOptions;
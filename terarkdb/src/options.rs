@@ -1,11 +1,16 @@
-use std::{ffi::c_int, ptr::NonNull};
+use std::{
+    ffi::{c_int, c_uchar},
+    ptr::NonNull,
+};
 
 use terarkdb_sys::{
-    rocksdb_options_create, rocksdb_options_destroy, rocksdb_options_increase_parallelism,
-    rocksdb_options_set_block_based_table_factory, rocksdb_options_t,
+    rocksdb_options_create, rocksdb_options_destroy, rocksdb_options_enable_statistics,
+    rocksdb_options_increase_parallelism, rocksdb_options_set_block_based_table_factory,
+    rocksdb_options_set_create_if_missing, rocksdb_options_statistics_get_string,
+    rocksdb_options_t,
 };
 
-use crate::BlockBasedTableOptions;
+use crate::{util::malloced_string, BlockBasedTableOptions};
 
 #[derive(Debug)]
 pub struct Options {
@@ -36,6 +41,16 @@ impl Options {
         self
     }
 
+    pub fn set_create_if_missing(&mut self, create_if_missing: bool) -> &mut Self {
+        unsafe {
+            rocksdb_options_set_create_if_missing(
+                self.as_mut_ptr(),
+                c_uchar::from(create_if_missing),
+            );
+        }
+        self
+    }
+
     pub fn set_block_based_table_options(
         &mut self,
         table_options: &BlockBasedTableOptions,
@@ -49,6 +64,21 @@ impl Options {
         self
     }
 
+    /// Collects statistics in a shared object, which is also used by
+    /// databases opened with these options.
+    pub fn enable_statistics(&mut self) -> &mut Self {
+        unsafe {
+            rocksdb_options_enable_statistics(self.as_mut_ptr());
+        }
+        self
+    }
+
+    /// Dumps the collected statistics, or `None` if statistics are not
+    /// enabled.
+    pub fn statistics_string(&self) -> Option<String> {
+        unsafe { malloced_string(rocksdb_options_statistics_get_string(self.inner.as_ptr())) }
+    }
+
     pub fn as_ptr(&self) -> *const rocksdb_options_t {
         self.inner.as_ptr()
     }
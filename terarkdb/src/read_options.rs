@@ -1,25 +1,106 @@
-use std::ptr::NonNull;
+use std::{
+    ffi::{c_char, c_uchar},
+    marker::PhantomData,
+    ptr::{self, NonNull},
+};
 
 use terarkdb_sys::{
-    rocksdb_readoptions_create, rocksdb_readoptions_destroy, rocksdb_readoptions_t,
+    rocksdb_readoptions_create, rocksdb_readoptions_destroy,
+    rocksdb_readoptions_set_iterate_lower_bound, rocksdb_readoptions_set_iterate_upper_bound,
+    rocksdb_readoptions_set_prefix_same_as_start, rocksdb_readoptions_set_snapshot,
+    rocksdb_readoptions_t,
 };
 
+use crate::snapshot::Snapshot;
+
 #[derive(Debug)]
-pub struct ReadOptions {
+pub struct ReadOptions<'snapshot> {
     inner: NonNull<rocksdb_readoptions_t>,
+    snapshot: PhantomData<&'snapshot Snapshot<'snapshot>>,
+    // The C API does not copy bounds, so keep them alive (and pinned) here.
+    lower_bound: Option<Box<[u8]>>,
+    upper_bound: Option<Box<[u8]>>,
+}
+
+fn bound_parts(bound: Option<&[u8]>) -> (*const c_char, usize) {
+    bound.map_or((ptr::null(), 0), |bound| {
+        (bound.as_ptr().cast::<c_char>(), bound.len())
+    })
 }
 
-impl Default for ReadOptions {
-    fn default() -> ReadOptions {
+impl Default for ReadOptions<'_> {
+    fn default() -> Self {
         ReadOptions::new()
     }
 }
 
-impl ReadOptions {
-    pub fn new() -> ReadOptions {
+impl<'snapshot> ReadOptions<'snapshot> {
+    pub fn new() -> ReadOptions<'snapshot> {
         ReadOptions {
             inner: NonNull::new(unsafe { rocksdb_readoptions_create() }).unwrap(),
+            snapshot: PhantomData,
+            lower_bound: None,
+            upper_bound: None,
+        }
+    }
+
+    /// Read from the given snapshot instead of the latest state.
+    pub fn set_snapshot(&mut self, snapshot: &'snapshot Snapshot<'_>) -> &mut Self {
+        unsafe {
+            rocksdb_readoptions_set_snapshot(self.as_mut_ptr(), snapshot.as_ptr());
+        }
+        self
+    }
+
+    /// Inclusive lower bound for iterators.
+    pub fn set_iterate_lower_bound<K: AsRef<[u8]>>(&mut self, key: Option<K>) -> &mut Self {
+        self.lower_bound = key.map(|key| Box::from(key.as_ref()));
+        let (key_ptr, key_len) = bound_parts(self.lower_bound.as_deref());
+        unsafe {
+            rocksdb_readoptions_set_iterate_lower_bound(self.as_mut_ptr(), key_ptr, key_len);
+        }
+        self
+    }
+
+    /// Exclusive upper bound for iterators.
+    pub fn set_iterate_upper_bound<K: AsRef<[u8]>>(&mut self, key: Option<K>) -> &mut Self {
+        self.upper_bound = key.map(|key| Box::from(key.as_ref()));
+        let (key_ptr, key_len) = bound_parts(self.upper_bound.as_deref());
+        unsafe {
+            rocksdb_readoptions_set_iterate_upper_bound(self.as_mut_ptr(), key_ptr, key_len);
+        }
+        self
+    }
+
+    /// Restricts iterators to keys starting with `prefix`, by setting both
+    /// bounds.
+    pub fn set_iterate_prefix<K: AsRef<[u8]>>(&mut self, prefix: K) -> &mut Self {
+        let prefix = prefix.as_ref();
+        let mut successor = prefix.to_vec();
+        while successor.last() == Some(&0xff) {
+            successor.pop();
+        }
+        // No upper bound if the prefix consists only of 0xff bytes.
+        let upper_bound = if let Some(last) = successor.last_mut() {
+            *last += 1;
+            Some(successor)
+        } else {
+            None
+        };
+        self.set_iterate_lower_bound(Some(prefix))
+            .set_iterate_upper_bound(upper_bound)
+    }
+
+    /// Stop iterators once they leave the prefix of the seek key, as
+    /// determined by the prefix extractor of the database.
+    pub fn set_prefix_same_as_start(&mut self, prefix_same_as_start: bool) -> &mut Self {
+        unsafe {
+            rocksdb_readoptions_set_prefix_same_as_start(
+                self.as_mut_ptr(),
+                c_uchar::from(prefix_same_as_start),
+            );
         }
+        self
     }
 
     pub(crate) fn as_ptr(&self) -> *const rocksdb_readoptions_t {
@@ -31,7 +112,7 @@ impl ReadOptions {
     }
 }
 
-impl Drop for ReadOptions {
+impl Drop for ReadOptions<'_> {
     fn drop(&mut self) {
         unsafe {
             rocksdb_readoptions_destroy(self.as_mut_ptr());
@@ -39,5 +120,5 @@ impl Drop for ReadOptions {
     }
 }
 
-unsafe impl Send for ReadOptions {}
-unsafe impl Sync for ReadOptions {}
+unsafe impl Send for ReadOptions<'_> {}
+unsafe impl Sync for ReadOptions<'_> {}
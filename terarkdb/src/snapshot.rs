@@ -0,0 +1,38 @@
+use std::ptr::NonNull;
+
+use terarkdb_sys::{rocksdb_create_snapshot, rocksdb_release_snapshot, rocksdb_snapshot_t};
+
+use crate::db::Db;
+
+/// Consistent point-in-time view of a [`Db`], for use with
+/// [`ReadOptions::set_snapshot()`](crate::ReadOptions::set_snapshot).
+#[derive(Debug)]
+pub struct Snapshot<'db> {
+    inner: NonNull<rocksdb_snapshot_t>,
+    db: &'db Db,
+}
+
+impl<'db> Snapshot<'db> {
+    pub fn new(db: &'db Db) -> Snapshot<'db> {
+        Snapshot {
+            inner: NonNull::new(unsafe { rocksdb_create_snapshot(db.as_mut_ptr()) }.cast_mut())
+                .unwrap(),
+            db,
+        }
+    }
+
+    pub(crate) fn as_ptr(&self) -> *const rocksdb_snapshot_t {
+        self.inner.as_ptr()
+    }
+}
+
+impl Drop for Snapshot<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            rocksdb_release_snapshot(self.db.as_mut_ptr(), self.as_ptr());
+        }
+    }
+}
+
+unsafe impl Send for Snapshot<'_> {}
+unsafe impl Sync for Snapshot<'_> {}
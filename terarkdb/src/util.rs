@@ -1,6 +1,6 @@
 use core::slice;
 use std::{
-    ffi::{c_char, c_void},
+    ffi::{c_char, c_void, CStr},
     marker::PhantomData,
     ops::{Deref, DerefMut},
     ptr::NonNull,
@@ -49,6 +49,13 @@ impl<T> Drop for Malloced<T> {
     }
 }
 
+/// Takes ownership of a NUL-terminated string allocated by the library.
+pub(crate) unsafe fn malloced_string(ptr_or_null: *mut c_char) -> Option<String> {
+    let ptr = unsafe { Malloced::new(ptr_or_null) }?;
+    let s = unsafe { CStr::from_ptr(ptr.as_ptr()) };
+    Some(s.to_string_lossy().into_owned())
+}
+
 pub struct MallocedBytes {
     ptr: Malloced<c_char>,
     len: usize,
@@ -0,0 +1,162 @@
+use std::{
+    any::Any,
+    ffi::{c_char, c_void},
+    panic::{self, AssertUnwindSafe},
+    ptr::NonNull,
+    slice,
+};
+
+use terarkdb_sys::{
+    rocksdb_writebatch_clear, rocksdb_writebatch_count, rocksdb_writebatch_create,
+    rocksdb_writebatch_delete, rocksdb_writebatch_destroy, rocksdb_writebatch_iterate,
+    rocksdb_writebatch_put, rocksdb_writebatch_t,
+};
+
+/// Operation recorded in a [`WriteBatch`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WriteBatchRecord<'a> {
+    Put { key: &'a [u8], value: &'a [u8] },
+    Delete { key: &'a [u8] },
+}
+
+/// Collection of updates that are applied atomically with
+/// [`Db::write()`](crate::Db::write).
+#[derive(Debug)]
+pub struct WriteBatch {
+    inner: NonNull<rocksdb_writebatch_t>,
+}
+
+impl Default for WriteBatch {
+    fn default() -> WriteBatch {
+        WriteBatch::new()
+    }
+}
+
+struct IterateState<'f> {
+    f: &'f mut dyn FnMut(WriteBatchRecord<'_>),
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+impl IterateState<'_> {
+    fn call(&mut self, record: WriteBatchRecord<'_>) {
+        if self.panic.is_none() {
+            // Must not unwind into C++.
+            if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| (self.f)(record))) {
+                self.panic = Some(panic);
+            }
+        }
+    }
+}
+
+unsafe fn bytes<'a>(ptr: *const c_char, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        unsafe { slice::from_raw_parts(ptr.cast::<u8>(), len) }
+    }
+}
+
+unsafe extern "C" fn iterate_put(
+    state: *mut c_void,
+    key: *const c_char,
+    key_len: usize,
+    value: *const c_char,
+    value_len: usize,
+) {
+    let state = unsafe { &mut *state.cast::<IterateState<'_>>() };
+    state.call(WriteBatchRecord::Put {
+        key: unsafe { bytes(key, key_len) },
+        value: unsafe { bytes(value, value_len) },
+    });
+}
+
+unsafe extern "C" fn iterate_delete(state: *mut c_void, key: *const c_char, key_len: usize) {
+    let state = unsafe { &mut *state.cast::<IterateState<'_>>() };
+    state.call(WriteBatchRecord::Delete {
+        key: unsafe { bytes(key, key_len) },
+    });
+}
+
+impl WriteBatch {
+    pub fn new() -> WriteBatch {
+        WriteBatch {
+            inner: NonNull::new(unsafe { rocksdb_writebatch_create() }).unwrap(),
+        }
+    }
+
+    pub fn put<K: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, key: K, value: V) -> &mut Self {
+        let key = key.as_ref();
+        let value = value.as_ref();
+        unsafe {
+            rocksdb_writebatch_put(
+                self.as_mut_ptr(),
+                key.as_ptr().cast::<c_char>(),
+                key.len(),
+                value.as_ptr().cast::<c_char>(),
+                value.len(),
+            );
+        }
+        self
+    }
+
+    pub fn delete<K: AsRef<[u8]>>(&mut self, key: K) -> &mut Self {
+        let key = key.as_ref();
+        unsafe {
+            rocksdb_writebatch_delete(self.as_mut_ptr(), key.as_ptr().cast::<c_char>(), key.len());
+        }
+        self
+    }
+
+    pub fn clear(&mut self) {
+        unsafe {
+            rocksdb_writebatch_clear(self.as_mut_ptr());
+        }
+    }
+
+    /// Number of operations in the batch.
+    pub fn count(&self) -> usize {
+        usize::try_from(unsafe { rocksdb_writebatch_count(self.as_implied_const_ptr()) }).unwrap()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+
+    /// Calls `f` for each operation in the batch, in insertion order.
+    pub fn iterate<F: FnMut(WriteBatchRecord<'_>)>(&self, mut f: F) {
+        let mut state = IterateState {
+            f: &mut f,
+            panic: None,
+        };
+        unsafe {
+            rocksdb_writebatch_iterate(
+                self.as_implied_const_ptr(),
+                (&mut state as *mut IterateState<'_>).cast::<c_void>(),
+                Some(iterate_put),
+                Some(iterate_delete),
+            );
+        }
+        if let Some(panic) = state.panic {
+            panic::resume_unwind(panic);
+        }
+    }
+
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut rocksdb_writebatch_t {
+        self.inner.as_ptr()
+    }
+
+    pub(crate) fn as_implied_const_ptr(&self) -> *mut rocksdb_writebatch_t {
+        self.inner.as_ptr()
+    }
+}
+
+impl Drop for WriteBatch {
+    fn drop(&mut self) {
+        unsafe {
+            rocksdb_writebatch_destroy(self.as_mut_ptr());
+        }
+    }
+}
+
+unsafe impl Send for WriteBatch {}
+unsafe impl Sync for WriteBatch {}
@@ -0,0 +1,64 @@
+use std::{
+    ffi::{c_int, c_uchar},
+    ptr::NonNull,
+};
+
+use terarkdb_sys::{
+    rocksdb_writeoptions_create, rocksdb_writeoptions_destroy, rocksdb_writeoptions_disable_WAL,
+    rocksdb_writeoptions_set_sync, rocksdb_writeoptions_t,
+};
+
+#[derive(Debug)]
+pub struct WriteOptions {
+    inner: NonNull<rocksdb_writeoptions_t>,
+}
+
+impl Default for WriteOptions {
+    fn default() -> WriteOptions {
+        WriteOptions::new()
+    }
+}
+
+impl WriteOptions {
+    pub fn new() -> WriteOptions {
+        WriteOptions {
+            inner: NonNull::new(unsafe { rocksdb_writeoptions_create() }).unwrap(),
+        }
+    }
+
+    /// Flush the operating system buffers before considering the write
+    /// complete.
+    pub fn set_sync(&mut self, sync: bool) -> &mut Self {
+        unsafe {
+            rocksdb_writeoptions_set_sync(self.as_mut_ptr(), c_uchar::from(sync));
+        }
+        self
+    }
+
+    /// Skip the write-ahead log. Recent writes may be lost on crash.
+    pub fn disable_wal(&mut self, disable: bool) -> &mut Self {
+        unsafe {
+            rocksdb_writeoptions_disable_WAL(self.as_mut_ptr(), c_int::from(disable));
+        }
+        self
+    }
+
+    pub(crate) fn as_ptr(&self) -> *const rocksdb_writeoptions_t {
+        self.inner.as_ptr()
+    }
+
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut rocksdb_writeoptions_t {
+        self.inner.as_ptr()
+    }
+}
+
+impl Drop for WriteOptions {
+    fn drop(&mut self) {
+        unsafe {
+            rocksdb_writeoptions_destroy(self.as_mut_ptr());
+        }
+    }
+}
+
+unsafe impl Send for WriteOptions {}
+unsafe impl Sync for WriteOptions {}